@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+/// We keep at most this much of the body of a rejected handshake response.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const MAX_REJECTION_BODY_SIZE: usize = 64 * 1024;
+
+/// The underlying cause of an [`Error`].
+///
+/// Reference-counted so that [`Error`] (and thus [`crate::WsEvent`]) can be cloned.
+pub type ErrorSource = Arc<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Something that went wrong when connecting, sending or receiving.
+///
+/// The [`std::fmt::Display`] message does not include the underlying cause.
+/// Use [`std::error::Error::source`] to get to it.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The URL could not be parsed, or is not a valid WebSocket URL.
+    InvalidUrl {
+        /// The URL that was passed to `connect`.
+        url: String,
+
+        /// Why the URL was rejected.
+        source: ErrorSource,
+    },
+
+    /// DNS lookup or the TCP connection to the server failed.
+    Connect(ErrorSource),
+
+    /// Connecting took longer than [`crate::Options::connect_timeout`].
+    ConnectTimeout {
+        /// The timeout that was exceeded.
+        timeout: std::time::Duration,
+    },
+
+    /// Setting up TLS or the TLS handshake failed.
+    Tls(ErrorSource),
+
+    /// The server answered the WebSocket upgrade request with a non-101 HTTP status.
+    ///
+    /// For instance `401 Unauthorized` or `403 Forbidden` if your credentials were rejected,
+    /// or `503 Service Unavailable` if the server is overloaded.
+    ///
+    /// Only on native. Browsers don't tell us why the handshake failed.
+    HandshakeRejected {
+        /// The HTTP status code, e.g. `401`.
+        status: u16,
+
+        /// The HTTP headers of the response.
+        headers: Vec<(String, String)>,
+
+        /// The start of the response body, if any.
+        ///
+        /// This is what the server sent along with the headers, truncated to 64 kilobytes.
+        body: Vec<u8>,
+    },
+
+    /// The peer violated the WebSocket protocol, or a size limit was exceeded.
+    Protocol(ErrorSource),
+
+    /// Reading from or writing to the socket failed.
+    Io(ErrorSource),
+
+    /// The server did not send anything within [`crate::Keepalive::timeout`] after we pinged it,
+    /// or did not reply within [`crate::Heartbeat::timeout`] to our heartbeat.
+    KeepaliveTimeout {
+        /// The timeout that was exceeded.
+        timeout: std::time::Duration,
+    },
+
+    /// Failed to spawn the background thread.
+    ///
+    /// With the `tokio` feature: there is no tokio runtime to spawn the connection task on
+    /// (see `connect_on`).
+    ///
+    /// Only on native.
+    SpawnThread(ErrorSource),
+
+    /// `connect_blocking` was called on a `current_thread` tokio runtime,
+    /// where blocking would keep the connection from ever making progress.
+    ///
+    /// `connect_blocking` needs a multi-thread runtime. Use `connect_async` instead.
+    ///
+    /// Only with the `tokio` feature.
+    BlockingOnCurrentThread,
+
+    /// Some of the [`crate::Options`] can't be used,
+    /// e.g. a [`crate::Heartbeat::payload`] that is neither text nor binary.
+    InvalidOptions(String),
+
+    /// A call to the browser `WebSocket` API failed, or the browser reported an error.
+    ///
+    /// Only on web.
+    Web(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl { url, .. } => write!(f, "Invalid WebSocket URL {url:?}"),
+            Self::Connect(_) => f.write_str("Failed to connect"),
+            Self::ConnectTimeout { timeout } => write!(f, "Failed to connect within {timeout:?}"),
+            Self::Tls(_) => f.write_str("TLS failed"),
+            Self::HandshakeRejected { status, .. } => {
+                write!(
+                    f,
+                    "Server rejected the WebSocket handshake with HTTP {status}"
+                )
+            }
+            Self::Protocol(_) => f.write_str("WebSocket protocol error"),
+            Self::KeepaliveTimeout { timeout } => {
+                write!(
+                    f,
+                    "No answer to keepalive ping or heartbeat within {timeout:?}"
+                )
+            }
+            Self::Io(_) => f.write_str("Reading from or writing to the socket failed"),
+            Self::SpawnThread(_) => f.write_str("Failed to spawn thread"),
+            Self::BlockingOnCurrentThread => {
+                f.write_str("connect_blocking needs a multi-thread tokio runtime")
+            }
+            Self::InvalidOptions(message) => write!(f, "Invalid options: {message}"),
+            Self::Web(message) => message.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidUrl { source, .. }
+            | Self::Connect(source)
+            | Self::Tls(source)
+            | Self::Protocol(source)
+            | Self::Io(source)
+            | Self::SpawnThread(source) => Some(source.as_ref()),
+            Self::ConnectTimeout { .. }
+            | Self::HandshakeRejected { .. }
+            | Self::KeepaliveTimeout { .. }
+            | Self::BlockingOnCurrentThread
+            | Self::InvalidOptions(_)
+            | Self::Web(_) => None,
+        }
+    }
+}
@@ -1,22 +1,62 @@
 //! Native implementation of the WebSocket client using the `tungstenite` crate.
+//!
+//! Each connection runs on its own thread, which sleeps until the socket is ready
+//! or a [`WsSender`] wakes it up with a message to send.
+//!
+//! With [`Options::split_threads`], outgoing messages are instead written by a second thread,
+//! so that a slow event handler doesn't hold them up.
+//!
+//! With [`Options::shared_reactor`], many connections share a single thread instead.
+
+mod reactor;
 
-use std::net::TcpStream;
 use std::{
     ops::ControlFlow,
-    sync::mpsc::{Receiver, TryRecvError},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, TryRecvError},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+use parking_lot::{Condvar, Mutex};
 use tungstenite::stream::MaybeTlsStream;
 use tungstenite::WebSocket;
 
-use crate::tungstenite_common::into_requester;
-use crate::{EventHandler, Options, Result, WsEvent, WsMessage};
+use crate::tungstenite_common::{
+    close_info, connect_error, from_tungstenite, into_requester, into_tungstenite_message,
+    open_info, parse_uri, redirect_target, KeepaliveTimer,
+};
+use crate::{CloseInfo, Error, EventHandler, OpenInfo, Options, Result, WsEvent, WsMessage};
+
+type Socket = WebSocket<MaybeTlsStream<mio::net::TcpStream>>;
 
 /// This is how you send [`WsMessage`]s to the server.
 ///
 /// When the last clone of this is dropped, the connection is closed.
 pub struct WsSender {
     tx: Option<std::sync::mpsc::Sender<WsMessage>>,
+
+    /// Wakes up whoever runs the connection.
+    waker: Option<Waker>,
+
+    /// Set once this is closed, so that we don't reconnect while waiting to.
+    closed: Arc<AtomicBool>,
+}
+
+/// Wakes up whoever runs the connection of a [`WsSender`].
+enum Waker {
+    /// The thread of the connection.
+    Thread(Arc<mio::Waker>),
+
+    /// The reading thread of [`Options::split_threads`].
+    ///
+    /// Only woken up when closing, since the writing thread sends the messages.
+    ReadingThread(Arc<mio::Waker>),
+
+    /// The shared reactor thread, which then only looks at this connection.
+    Reactor(reactor::ConnectionWaker),
 }
 
 impl Drop for WsSender {
@@ -33,17 +73,35 @@ impl WsSender {
     pub fn send(&mut self, msg: WsMessage) {
         if let Some(tx) = &self.tx {
             tx.send(msg).ok();
+            if !matches!(self.waker, Some(Waker::ReadingThread(_))) {
+                self.wake();
+            }
         }
     }
 
+    /// Close the connection with the given close code and reason.
+    ///
+    /// The server is expected to answer with its own close frame, after which
+    /// [`WsEvent::Closed`] is reported. If it doesn't answer within
+    /// [`Options::close_timeout`], the closure is reported as abnormal (code `1006`).
+    pub fn close_with(&mut self, code: u16, reason: impl Into<String>) {
+        self.send(WsMessage::Close {
+            code,
+            reason: reason.into(),
+        });
+        self.close();
+    }
+
     /// Close the connection.
     ///
     /// This is called automatically when the sender is dropped.
     pub fn close(&mut self) {
         if self.tx.is_some() {
             log::debug!("Closing WebSocket");
+            self.tx = None;
+            self.closed.store(true, Ordering::Release);
+            self.wake();
         }
-        self.tx = None;
     }
 
     /// Forget about this sender without closing the connection.
@@ -51,23 +109,42 @@ impl WsSender {
         #[allow(clippy::mem_forget)] // intentional
         std::mem::forget(self.tx.take());
     }
+
+    fn wake(&self) {
+        match &self.waker {
+            Some(Waker::Thread(waker) | Waker::ReadingThread(waker)) => {
+                if let Err(err) = waker.wake() {
+                    log::warn!("Failed to wake up the WebSocket thread: {err}");
+                }
+            }
+            Some(Waker::Reactor(waker)) => waker.wake(),
+            None => {}
+        }
+    }
 }
 
 pub(crate) fn ws_receive_impl(url: String, options: Options, on_event: EventHandler) -> Result<()> {
+    if options.shared_reactor {
+        return reactor::ws_receive_impl(url, options, on_event);
+    }
+
+    let mut poller = Poller::new()?;
+
     std::thread::Builder::new()
         .name("ewebsock".to_owned())
         .spawn(move || {
-            if let Err(err) = ws_receiver_blocking(&url, options, &on_event) {
-                #[expect(
-                    unused_must_use,
-                    reason = "we intentionally ignore the return of `on_event`"
-                )]
-                on_event(WsEvent::Error(err));
-            } else {
-                log::debug!("WebSocket connection closed.");
-            }
+            run_with_reconnect(
+                &url,
+                &options,
+                &on_event,
+                &mut poller,
+                &AtomicBool::new(false),
+                |socket, open_info, poller| {
+                    run_connection(socket, open_info, &options, &on_event, None, poller)
+                },
+            );
         })
-        .map_err(|err| format!("Failed to spawn thread: {err}"))?;
+        .map_err(|err| Error::SpawnThread(Arc::new(err)))?;
 
     Ok(())
 }
@@ -76,55 +153,15 @@ pub(crate) fn ws_receive_impl(url: String, options: Options, on_event: EventHand
 ///
 /// Blocking version of [`crate::ws_receive`], only available on native.
 ///
+/// This makes a single connection, ignoring [`Options::reconnect`].
+///
 /// # Errors
 /// All errors are returned to the caller, and NOT reported via `on_event`.
+#[allow(clippy::needless_pass_by_value)] // For backwards compatibility
 pub fn ws_receiver_blocking(url: &str, options: Options, on_event: &EventHandler) -> Result<()> {
-    let uri: tungstenite::http::Uri = url
-        .parse()
-        .map_err(|err| format!("Failed to parse URL {url:?}: {err}"))?;
-    let config = tungstenite::protocol::WebSocketConfig::from(options.clone());
-    let max_redirects = 3; // tungstenite default
-
-    let read_timeout = options.read_timeout;
-    let (mut socket, response) = match tungstenite::client::connect_with_config(
-        into_requester(uri, options),
-        Some(config),
-        max_redirects,
-    ) {
-        Ok(result) => result,
-        Err(err) => {
-            return Err(format!("Connect: {err}"));
-        }
-    };
-
-    set_read_timeout(&mut socket, read_timeout)?;
-
-    log::debug!("WebSocket HTTP response code: {}", response.status());
-    log::trace!(
-        "WebSocket response contains the following headers: {:?}",
-        response.headers()
-    );
-
-    let control = on_event(WsEvent::Opened);
-    if control.is_break() {
-        log::trace!("Closing connection due to Break");
-        return socket
-            .close(None)
-            .map_err(|err| format!("Failed to close connection: {err}"));
-    }
-
-    loop {
-        let control = read_from_socket(&mut socket, on_event)?;
-
-        if control.is_break() {
-            log::trace!("Closing connection due to Break");
-            return socket
-                .close(None)
-                .map_err(|err| format!("Failed to close connection: {err}"));
-        }
-
-        std::thread::yield_now();
-    }
+    let mut poller = Poller::new()?;
+    let (socket, open_info) = connect(url, &options, poller.registry(), SOCKET)?;
+    run_connection(socket, open_info, &options, on_event, None, &mut poller).map(|_| ())
 }
 
 pub(crate) fn ws_connect_impl(
@@ -132,57 +169,173 @@ pub(crate) fn ws_connect_impl(
     options: Options,
     on_event: EventHandler,
 ) -> Result<WsSender> {
+    if options.shared_reactor {
+        return reactor::ws_connect_impl(url, options, on_event);
+    }
+
     let (tx, rx) = std::sync::mpsc::channel();
+    let mut poller = Poller::new()?;
+    let waker = poller.waker()?;
 
+    if options.split_threads {
+        return ws_connect_split(url, options, on_event, tx, rx, poller, waker);
+    }
+
+    let closed = Arc::new(AtomicBool::new(false));
     std::thread::Builder::new()
         .name("ewebsock".to_owned())
-        .spawn(move || {
-            if let Err(err) = ws_connect_blocking(&url, options, &on_event, &rx) {
-                #[expect(
-                    unused_must_use,
-                    reason = "we intentionally ignore the return of `on_event`"
-                )]
-                on_event(WsEvent::Error(err));
-            } else {
-                log::debug!("WebSocket connection closed.");
+        .spawn({
+            let closed = closed.clone();
+            move || {
+                run_with_reconnect(
+                    &url,
+                    &options,
+                    &on_event,
+                    &mut poller,
+                    &closed,
+                    |socket, open_info, poller| {
+                        run_connection(socket, open_info, &options, &on_event, Some(&rx), poller)
+                    },
+                );
             }
         })
-        .map_err(|err| format!("Failed to spawn thread: {err}"))?;
+        .map_err(|err| Error::SpawnThread(Arc::new(err)))?;
 
-    Ok(WsSender { tx: Some(tx) })
+    Ok(WsSender {
+        tx: Some(tx),
+        waker: Some(Waker::Thread(waker)),
+        closed,
+    })
 }
 
 /// Connect and call the given event handler on each received event.
 ///
 /// This is a blocking variant of [`crate::ws_connect`], only available on native.
 ///
+/// This makes a single connection, ignoring [`Options::reconnect`].
+///
+/// Since nothing wakes us up when a message is sent on the other end of `rx`,
+/// we check for outgoing messages every [`Options::read_timeout`].
+///
 /// # Errors
 /// All errors are returned to the caller, and NOT reported via `on_event`.
+#[allow(clippy::needless_pass_by_value)] // For backwards compatibility
 pub fn ws_connect_blocking(
     url: &str,
     options: Options,
     on_event: &EventHandler,
     rx: &Receiver<WsMessage>,
 ) -> Result<()> {
-    let config = tungstenite::protocol::WebSocketConfig::from(options.clone());
-    let max_redirects = 3; // tungstenite default
-    let uri: tungstenite::http::Uri = url
-        .parse()
-        .map_err(|err| format!("Failed to parse URL {url:?}: {err}"))?;
-
-    let read_timeout = options.read_timeout;
-    let (mut socket, response) = match tungstenite::client::connect_with_config(
-        into_requester(uri, options),
-        Some(config),
-        max_redirects,
-    ) {
-        Ok(result) => result,
-        Err(err) => {
-            return Err(format!("Connect: {err}"));
+    let mut poller = Poller::new()?;
+    let (socket, open_info) = connect(url, &options, poller.registry(), SOCKET)?;
+    run_connection(socket, open_info, &options, on_event, Some(rx), &mut poller).map(|_| ())
+}
+
+/// Connect, and then reconnect according to [`Options::reconnect`] whenever the connection is lost.
+///
+/// Each connection is handed to `run_connection`, which returns once it is closed.
+/// We never reconnect once `sender_closed` is set, not even if it is set while we wait to.
+///
+/// All errors are reported via `on_event`.
+fn run_with_reconnect(
+    url: &str,
+    options: &Options,
+    on_event: &EventHandler,
+    poller: &mut Poller,
+    sender_closed: &AtomicBool,
+    mut run_connection: impl FnMut(Socket, OpenInfo, &mut Poller) -> Result<ControlFlow<()>>,
+) {
+    let mut attempt = 0;
+
+    loop {
+        let result =
+            connect(url, options, poller.registry(), SOCKET).and_then(|(socket, open_info)| {
+                attempt = 0;
+                run_connection(socket, open_info, poller)
+            });
+
+        match result {
+            Ok(ControlFlow::Break(())) => {
+                log::debug!("WebSocket connection closed.");
+                return;
+            }
+            Ok(ControlFlow::Continue(())) => {
+                log::debug!("WebSocket connection closed by the server.");
+            }
+            Err(err) => {
+                if on_event(WsEvent::Error(err)).is_break()
+                    || on_event(WsEvent::Closed(CloseInfo::abnormal())).is_break()
+                {
+                    return;
+                }
+            }
         }
-    };
 
-    set_read_timeout(&mut socket, read_timeout)?;
+        if sender_closed.load(Ordering::Acquire) {
+            log::debug!("WsSender closed - not reconnecting.");
+            return;
+        }
+        let Some(reconnect) = &options.reconnect else {
+            return;
+        };
+        attempt += 1;
+        let Some(delay) = reconnect.delay(attempt) else {
+            log::debug!("Giving up after {} reconnection attempts", attempt - 1);
+            return;
+        };
+        if on_event(WsEvent::Reconnecting { attempt, delay }).is_break() {
+            return;
+        }
+
+        // Closing the `WsSender` wakes us up:
+        let reconnect_at = Instant::now() + delay;
+        while !sender_closed.load(Ordering::Acquire) && Instant::now() < reconnect_at {
+            if let Err(err) = poller.wait(Some(reconnect_at)) {
+                log::warn!("Failed to wait for the WsSender: {err:?}");
+                std::thread::sleep(reconnect_at.saturating_duration_since(Instant::now()));
+            }
+        }
+        if sender_closed.load(Ordering::Acquire) {
+            log::debug!("WsSender closed while waiting to reconnect.");
+            return;
+        }
+    }
+}
+
+/// Connect to the server and perform the WebSocket handshake.
+///
+/// The handshake is blocking, for at most [`Options::connect_timeout`].
+/// Once it is done, the socket is made non-blocking and registered with `registry` under `token`.
+fn connect(
+    url: &str,
+    options: &Options,
+    registry: &mio::Registry,
+    token: mio::Token,
+) -> Result<(Socket, OpenInfo)> {
+    let uri = parse_uri(url)?;
+
+    let started = Instant::now();
+    let deadline = options.connect_timeout.map(|timeout| started + timeout);
+    let (mut socket, response, uri) =
+        connect_following_redirects(uri, options, deadline).map_err(|err| {
+            match options.connect_timeout {
+                // Whatever went wrong, it was because we ran out of time:
+                Some(timeout) if started.elapsed() >= timeout => Error::ConnectTimeout { timeout },
+                _ => connect_error(url, err),
+            }
+        })?;
+
+    let stream = tcp_stream(socket.get_mut())?;
+    socket2::SockRef::from(&*stream)
+        .set_nonblocking(true)
+        .map_err(|err| Error::Io(Arc::new(err)))?;
+    registry
+        .register(
+            stream,
+            token,
+            mio::Interest::READABLE | mio::Interest::WRITABLE,
+        )
+        .map_err(|err| Error::Io(Arc::new(err)))?;
 
     log::debug!("WebSocket HTTP response code: {}", response.status());
     log::trace!(
@@ -190,121 +343,756 @@ pub fn ws_connect_blocking(
         response.headers()
     );
 
-    let control = on_event(WsEvent::Opened);
+    Ok((socket, open_info(&uri, &response)))
+}
+
+/// Perform the handshake, following redirects according to [`Options::redirects`].
+///
+/// Returns the URI we ended up at, along with the socket and the response.
+fn connect_following_redirects(
+    mut uri: tungstenite::http::Uri,
+    options: &Options,
+    deadline: Option<Instant>,
+) -> tungstenite::Result<(
+    Socket,
+    tungstenite::handshake::client::Response,
+    tungstenite::http::Uri,
+)> {
+    let mut num_redirects = 0;
+    loop {
+        match handshake(&uri, options, deadline) {
+            Ok((socket, response)) => return Ok((socket, response, uri)),
+            Err(tungstenite::Error::Http(response)) => {
+                let Some(target) =
+                    redirect_target(&uri, &response, &options.redirects, num_redirects)
+                else {
+                    return Err(tungstenite::Error::Http(response));
+                };
+                uri = target;
+                num_redirects += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Connect to `uri`, and perform a blocking WebSocket handshake.
+fn handshake(
+    uri: &tungstenite::http::Uri,
+    options: &Options,
+    deadline: Option<Instant>,
+) -> tungstenite::Result<(Socket, tungstenite::handshake::client::Response)> {
+    use tungstenite::handshake::HandshakeError;
+
+    let (stream, _watchdog) = crate::proxy::connect_tcp(options, uri, deadline)?;
+    // Still blocking, until the handshake is done:
+    let stream = mio::net::TcpStream::from_std(stream);
+
+    let request = into_requester(uri.clone(), options.clone());
+    let config = Some(tungstenite::protocol::WebSocketConfig::from(
+        options.clone(),
+    ));
+
+    #[cfg(any(
+        feature = "tls",
+        feature = "rustls-tls-native-roots",
+        feature = "native-tls"
+    ))]
+    let result = {
+        let connector = crate::tls::connector(&options.tls)?.map(Into::into);
+        tungstenite::client_tls_with_config(request, stream, config, connector)
+    };
+
+    #[cfg(not(any(
+        feature = "tls",
+        feature = "rustls-tls-native-roots",
+        feature = "native-tls"
+    )))]
+    let result = match tungstenite::client::uri_mode(uri)? {
+        tungstenite::stream::Mode::Plain => {
+            tungstenite::client::client_with_config(request, MaybeTlsStream::Plain(stream), config)
+        }
+        tungstenite::stream::Mode::Tls => {
+            return Err(tungstenite::Error::Url(
+                tungstenite::error::UrlError::TlsFeatureNotEnabled,
+            ))
+        }
+    };
+
+    result.map_err(|err| match err {
+        HandshakeError::Failure(err) => err,
+        HandshakeError::Interrupted(_) => {
+            // Only happens with non-blocking sockets
+            tungstenite::Error::Io(std::io::ErrorKind::WouldBlock.into())
+        }
+    })
+}
+
+/// The TCP stream under the TLS stream, if any.
+fn tcp_stream(
+    stream: &mut MaybeTlsStream<mio::net::TcpStream>,
+) -> Result<&mut mio::net::TcpStream> {
+    match stream {
+        MaybeTlsStream::Plain(stream) => Ok(stream),
+        #[cfg(feature = "native-tls")]
+        MaybeTlsStream::NativeTls(stream) => Ok(stream.get_mut()),
+        #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+        MaybeTlsStream::Rustls(stream) => Ok(&mut stream.sock),
+        #[allow(unreachable_patterns)] // Depends on what tungstenite features are enabled
+        _ => Err(Error::Tls(Arc::new(std::io::Error::other(
+            "Unsupported TLS stream",
+        )))),
+    }
+}
+
+/// Token for the socket of the current connection.
+const SOCKET: mio::Token = mio::Token(0);
+
+/// Token for a [`WsSender`] waking us up.
+const WAKER: mio::Token = mio::Token(1);
+
+/// Puts the connection thread to sleep until the socket is ready,
+/// or a [`WsSender`] wakes us up.
+struct Poller {
+    poll: mio::Poll,
+    events: mio::Events,
+
+    /// Set if a [`WsSender`] wakes us up whenever it has queued up a message.
+    ///
+    /// We keep our own reference, because dropping the last one deregisters it,
+    /// and then we could miss the wake-up from the [`WsSender`] being dropped.
+    waker: Option<Arc<mio::Waker>>,
+}
+
+impl Poller {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            poll: mio::Poll::new().map_err(|err| Error::Io(Arc::new(err)))?,
+            events: mio::Events::with_capacity(4),
+            waker: None,
+        })
+    }
+
+    /// Create the waker for a [`WsSender`].
+    fn waker(&mut self) -> Result<Arc<mio::Waker>> {
+        let waker =
+            mio::Waker::new(self.poll.registry(), WAKER).map_err(|err| Error::Io(Arc::new(err)))?;
+        let waker = Arc::new(waker);
+        self.waker = Some(waker.clone());
+        Ok(waker)
+    }
+
+    /// Where to register the socket of a new connection, under [`SOCKET`].
+    ///
+    /// The socket of the previous connection is deregistered when it is dropped.
+    fn registry(&self) -> &mio::Registry {
+        self.poll.registry()
+    }
+
+    /// Sleep until anything happens, or until the deadline.
+    ///
+    /// Callers should check everything afterwards: the socket, the outgoing messages, and the time.
+    fn wait(&mut self, deadline: Option<Instant>) -> Result<()> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match self.poll.poll(&mut self.events, timeout) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => Ok(()),
+            Err(err) => Err(Error::Io(Arc::new(err))),
+        }
+    }
+}
+
+/// Report [`WsEvent::Opened`], and then send and receive messages until the connection is closed.
+///
+/// Returns [`ControlFlow::Continue`] if the server closed the connection,
+/// and [`ControlFlow::Break`] if we closed it, because the [`WsSender`] was closed
+/// or `on_event` returned [`ControlFlow::Break`].
+///
+/// If `rx` is `None`, we only receive.
+fn run_connection(
+    mut socket: Socket,
+    open_info: OpenInfo,
+    options: &Options,
+    on_event: &EventHandler,
+    rx: Option<&Receiver<WsMessage>>,
+    poller: &mut Poller,
+) -> Result<ControlFlow<()>> {
+    let control = on_event(WsEvent::Opened(open_info));
     if control.is_break() {
         log::trace!("Closing connection due to Break");
-        return socket
-            .close(None)
-            .map_err(|err| format!("Failed to close connection: {err}"));
+        close(&mut socket)?;
+        return Ok(ControlFlow::Break(()));
+    }
+
+    let mut keepalive = options.keepalive.clone().map(KeepaliveTimer::new);
+
+    // If nobody wakes us up when a message is queued, we need to check regularly:
+    let check_rx_interval = if rx.is_some() && poller.waker.is_none() {
+        options.read_timeout.filter(|timeout| !timeout.is_zero())
+    } else {
+        None
+    };
+
+    loop {
+        match step(&mut socket, keepalive.as_mut(), rx, on_event)? {
+            Step::Open => {}
+            Step::Busy => continue,
+            Step::SentClose => {
+                wait_for_close_reply(&mut socket, options.close_timeout, on_event, poller)?;
+                return Ok(ControlFlow::Break(()));
+            }
+            Step::Closed(control) => return Ok(control),
+        }
+
+        let deadline = [
+            keepalive.as_ref().map(KeepaliveTimer::next_deadline),
+            check_rx_interval.map(|interval| Instant::now() + interval),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        poller.wait(deadline)?;
+    }
+}
+
+/// The state of a connection after a [`step`].
+enum Step {
+    /// Still open: wait for the socket, a [`WsSender`], or the keepalive, and then step again.
+    Open,
+
+    /// Still open, and there is more to read: step again soon, without waiting.
+    ///
+    /// See [`MAX_EVENTS_PER_STEP`].
+    Busy,
+
+    /// We sent a close frame, and should wait for the server to answer with its own.
+    SentClose,
+
+    /// The connection is closed.
+    ///
+    /// [`ControlFlow::Continue`] if the server closed it, and [`ControlFlow::Break`] if we did,
+    /// because the [`WsSender`] was closed or `on_event` returned [`ControlFlow::Break`].
+    Closed(ControlFlow<()>),
+}
+
+/// How many messages [`step`] reads before it gives the [`WsSender`],
+/// and the other connections on the shared reactor, a turn.
+const MAX_EVENTS_PER_STEP: usize = 64;
+
+/// Do everything that can be done without blocking: send what is queued up in `rx`,
+/// pass on what there is to read to `on_event`, and ping if it is time to.
+fn step(
+    socket: &mut Socket,
+    mut keepalive: Option<&mut KeepaliveTimer>,
+    rx: Option<&Receiver<WsMessage>>,
+    on_event: &EventHandler,
+) -> Result<Step> {
+    if let Some(rx) = rx {
+        if let Some(step) = send_queued(socket, rx)? {
+            return Ok(step);
+        }
+    }
+
+    // The socket only tells us when it _becomes_ readable, so we read all there is,
+    // or say that we are `Busy` if there is more than `MAX_EVENTS_PER_STEP`:
+    let mut num_read = 0;
+    let mut busy = false;
+    while let Some(control) = read_from_socket(socket, on_event)? {
+        if let Some(keepalive) = &mut keepalive {
+            keepalive.on_receive();
+        }
+
+        if control.is_break() {
+            log::trace!("Closing connection due to Break");
+            close(socket)?;
+            return Ok(Step::Closed(ControlFlow::Break(())));
+        }
+
+        if !socket.can_read() {
+            // The server closed the connection. Send our answer to its close frame:
+            flush(socket).ok();
+            return Ok(Step::Closed(ControlFlow::Continue(())));
+        }
+
+        num_read += 1;
+        if num_read == MAX_EVENTS_PER_STEP {
+            busy = true;
+            break;
+        }
+    }
+
+    if let Some(keepalive) = &mut keepalive {
+        if keepalive.check()? {
+            write(
+                socket,
+                tungstenite::protocol::Message::Ping(Default::default()),
+            )?;
+        }
     }
 
+    // Once the socket becomes writable, we send whatever didn't fit before:
+    flush(socket)?;
+
+    Ok(if busy { Step::Busy } else { Step::Open })
+}
+
+/// Send all messages queued up in `rx`, flushing once at the end.
+///
+/// Returns `None` if the connection is still open.
+fn send_queued(socket: &mut Socket, rx: &Receiver<WsMessage>) -> Result<Option<Step>> {
+    let mut wrote_any = false;
     loop {
         match rx.try_recv() {
             Ok(outgoing_message) => {
-                let outgoing_message = match outgoing_message {
-                    WsMessage::Text(text) => tungstenite::protocol::Message::Text(text.into()),
-                    WsMessage::Binary(data) => tungstenite::protocol::Message::Binary(data.into()),
-                    WsMessage::Ping(data) => tungstenite::protocol::Message::Ping(data.into()),
-                    WsMessage::Pong(data) => tungstenite::protocol::Message::Pong(data.into()),
-                    WsMessage::Unknown(_) => panic!("You cannot send WsMessage::Unknown"),
-                };
-                if let Err(err) = socket.send(outgoing_message) {
-                    socket.close(None).ok();
-                    socket.flush().ok();
-                    return Err(format!("send: {err}"));
+                let is_close = matches!(outgoing_message, WsMessage::Close { .. });
+                if let Err(err) = write(socket, into_tungstenite_message(outgoing_message)) {
+                    close(socket).ok();
+                    return Err(err);
+                }
+                wrote_any = true;
+                if is_close {
+                    flush(socket)?;
+                    return Ok(Some(Step::SentClose));
                 }
             }
             Err(TryRecvError::Disconnected) => {
                 log::debug!("WsSender dropped - closing connection.");
-                socket.close(None).ok();
-                socket.flush().ok();
-                return Ok(());
+                close(socket).ok();
+                return Ok(Some(Step::Closed(ControlFlow::Break(()))));
+            }
+            Err(TryRecvError::Empty) => {
+                if wrote_any {
+                    flush(socket)?;
+                }
+                return Ok(None);
             }
-            Err(TryRecvError::Empty) => {}
+        }
+    }
+}
+
+/// Read a message and pass it on to `on_event`, returning what `on_event` returned.
+///
+/// Returns `None` if there is nothing to read right now.
+fn read_from_socket(
+    socket: &mut Socket,
+    on_event: &EventHandler,
+) -> Result<Option<ControlFlow<()>>> {
+    Ok(read_event(socket)?.map(on_event))
+}
+
+/// Read the next message.
+///
+/// Returns `None` if there is nothing to read right now.
+fn read_event(socket: &mut Socket) -> Result<Option<WsEvent>> {
+    loop {
+        let event = match socket.read() {
+            Ok(incoming_msg) => match incoming_msg {
+                tungstenite::protocol::Message::Text(text) => {
+                    WsEvent::Message(WsMessage::Text(text.to_string()))
+                }
+                tungstenite::protocol::Message::Binary(data) => {
+                    WsEvent::Message(WsMessage::Binary(data.into()))
+                }
+                tungstenite::protocol::Message::Ping(data) => {
+                    WsEvent::Message(WsMessage::Ping(data.into()))
+                }
+                tungstenite::protocol::Message::Pong(data) => {
+                    WsEvent::Message(WsMessage::Pong(data.into()))
+                }
+                tungstenite::protocol::Message::Close(close) => {
+                    log::debug!("WebSocket close received: {close:?}");
+                    WsEvent::Closed(close_info(close))
+                }
+                tungstenite::protocol::Message::Frame(_) => continue,
+            },
+            Err(err) if is_would_block(&err) => return Ok(None),
+            Err(err) => return Err(from_tungstenite(err)),
         };
+        return Ok(Some(event));
+    }
+}
 
-        let control = read_from_socket(&mut socket, on_event)?;
+/// Like [`ws_connect_impl`], but with a separate thread for writing, see [`Options::split_threads`].
+fn ws_connect_split(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+    tx: std::sync::mpsc::Sender<WsMessage>,
+    rx: Receiver<WsMessage>,
+    mut poller: Poller,
+    waker: Arc<mio::Waker>,
+) -> Result<WsSender> {
+    let shared = Arc::new(SharedSocket {
+        state: Mutex::default(),
+        changed: Condvar::new(),
+        waker: waker.clone(),
+    });
 
-        if control.is_break() {
-            log::trace!("Closing connection due to Break");
-            return socket
-                .close(None)
-                .map_err(|err| format!("Failed to close connection: {err}"));
+    std::thread::Builder::new()
+        .name("ewebsock-writer".to_owned())
+        .spawn({
+            let shared = shared.clone();
+            let close_timeout = options.close_timeout;
+            move || write_queued(&shared, &rx, close_timeout)
+        })
+        .map_err(|err| Error::SpawnThread(Arc::new(err)))?;
+
+    let closed = Arc::new(AtomicBool::new(false));
+    std::thread::Builder::new()
+        .name("ewebsock".to_owned())
+        .spawn({
+            let closed = closed.clone();
+            move || {
+                run_with_reconnect(
+                    &url,
+                    &options,
+                    &on_event,
+                    &mut poller,
+                    &closed,
+                    |socket, open_info, poller| {
+                        run_connection_split(
+                            socket, open_info, &options, &on_event, &shared, poller,
+                        )
+                    },
+                );
+                shared.state.lock().done = true;
+                shared.changed.notify_all();
+            }
+        })
+        .map_err(|err| Error::SpawnThread(Arc::new(err)))?;
+
+    Ok(WsSender {
+        tx: Some(tx),
+        waker: Some(Waker::ReadingThread(waker)),
+        closed,
+    })
+}
+
+/// The connection shared by the reading and the writing thread, see [`Options::split_threads`].
+///
+/// The lock is only ever held while reading or writing the (non-blocking) socket,
+/// never while calling the event handler.
+struct SharedSocket {
+    state: Mutex<SharedState>,
+
+    /// Notified when a new connection is opened, or when we give up on connecting.
+    changed: Condvar,
+
+    /// Wakes up the reading thread.
+    waker: Arc<mio::Waker>,
+}
+
+#[derive(Default)]
+struct SharedState {
+    /// The current connection, if any.
+    socket: Option<Socket>,
+
+    /// Bumped each time a new connection is opened.
+    connection: u64,
+
+    /// Set by the writing thread once the [`WsSender`] is done.
+    closing: Option<Closing>,
+
+    /// Set by the reading thread once it won't (re)connect anymore.
+    done: bool,
+}
+
+enum Closing {
+    /// The [`WsSender`] was dropped.
+    SenderDropped,
+
+    /// We sent a close frame, and are waiting for the server to answer with its own.
+    WaitingForReply { deadline: Instant },
+}
+
+impl SharedSocket {
+    fn wake(&self) {
+        if let Err(err) = self.waker.wake() {
+            log::warn!("Failed to wake up the WebSocket thread: {err}");
         }
+    }
+}
+
+/// Runs on the writing thread: write messages from `rx` as soon as they arrive,
+/// waiting for the connection to be (re)opened when needed.
+///
+/// A message we fail to write is written again once we have reconnected.
+fn write_queued(shared: &SharedSocket, rx: &Receiver<WsMessage>, close_timeout: Duration) {
+    // The message we failed to write, and the connection we failed to write it to.
+    let mut failed: Option<(tungstenite::protocol::Message, u64)> = None;
+
+    loop {
+        let (outgoing_message, failed_connection) = match failed.take() {
+            Some((outgoing_message, connection)) => (outgoing_message, Some(connection)),
+            None => match rx.recv() {
+                Ok(outgoing_message) => (into_tungstenite_message(outgoing_message), None),
+                Err(_) => break,
+            },
+        };
+        let is_close = outgoing_message.is_close();
+
+        let mut state = shared.state.lock();
+        while (state.socket.is_none() || failed_connection == Some(state.connection)) && !state.done
+        {
+            shared.changed.wait(&mut state);
+        }
+        let connection = state.connection;
+        let Some(socket) = &mut state.socket else {
+            log::debug!("Connection is gone - no longer sending messages.");
+            return;
+        };
 
-        std::thread::yield_now();
+        // What doesn't fit into the socket right now is flushed by the reading thread.
+        if let Err(err) = write(socket, outgoing_message.clone()).and_then(|()| flush(socket)) {
+            // Like without `split_threads`, we give up on the connection,
+            // and the reading thread reports it broken.
+            log::debug!("Failed to send message, trying again once reconnected: {err:?}");
+            close(socket).ok();
+            failed = Some((outgoing_message, connection));
+            drop(state);
+            shared.wake();
+            continue;
+        }
+
+        if is_close {
+            state.closing = Some(Closing::WaitingForReply {
+                deadline: Instant::now() + close_timeout,
+            });
+            drop(state);
+            shared.wake();
+        }
     }
+
+    log::debug!("WsSender dropped - closing connection.");
+    shared
+        .state
+        .lock()
+        .closing
+        .get_or_insert(Closing::SenderDropped);
+    shared.wake();
 }
 
-fn read_from_socket(
-    socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+/// Like [`run_connection`], but the writing is done on another thread, see [`write_queued`].
+fn run_connection_split(
+    mut socket: Socket,
+    open_info: OpenInfo,
+    options: &Options,
     on_event: &EventHandler,
+    shared: &SharedSocket,
+    poller: &mut Poller,
 ) -> Result<ControlFlow<()>> {
-    let control = match socket.read() {
-        Ok(incoming_msg) => match incoming_msg {
-            tungstenite::protocol::Message::Text(text) => {
-                on_event(WsEvent::Message(WsMessage::Text(text.to_string())))
-            }
-            tungstenite::protocol::Message::Binary(data) => {
-                on_event(WsEvent::Message(WsMessage::Binary(data.into())))
+    let control = on_event(WsEvent::Opened(open_info));
+    if control.is_break() {
+        log::trace!("Closing connection due to Break");
+        close(&mut socket)?;
+        return Ok(ControlFlow::Break(()));
+    }
+
+    {
+        let mut state = shared.state.lock();
+        state.socket = Some(socket);
+        state.connection = state.connection.wrapping_add(1);
+    }
+    shared.changed.notify_all();
+
+    let result = read_shared(options, on_event, shared, poller);
+
+    // Drop the connection, so that the writing thread waits for the next one:
+    shared.state.lock().socket = None;
+
+    result
+}
+
+/// How many messages [`read_shared`] reads before it lets go of the lock for a while.
+const MAX_EVENTS_PER_LOCK: usize = 64;
+
+/// Runs on the reading thread: read messages and pass them on to `on_event`,
+/// until the connection is closed.
+fn read_shared(
+    options: &Options,
+    on_event: &EventHandler,
+    shared: &SharedSocket,
+    poller: &mut Poller,
+) -> Result<ControlFlow<()>> {
+    let mut keepalive = options.keepalive.clone().map(KeepaliveTimer::new);
+    let mut incoming = Vec::new();
+
+    loop {
+        let mut state = shared.state.lock();
+        let SharedState {
+            socket: Some(socket),
+            closing,
+            ..
+        } = &mut *state
+        else {
+            return Ok(ControlFlow::Break(()));
+        };
+
+        if matches!(closing, Some(Closing::SenderDropped)) {
+            log::debug!("WsSender dropped - closing connection.");
+            close(socket).ok();
+            return Ok(ControlFlow::Break(()));
+        }
+        let close_deadline = match closing {
+            Some(Closing::WaitingForReply { deadline }) => Some(*deadline),
+            _ => None,
+        };
+
+        // The socket only tells us when it _becomes_ readable, so we read all there is,
+        // at most `MAX_EVENTS_PER_LOCK` at a time.
+        // We call `on_event` only after releasing the lock, so the writing thread can keep going.
+        let mut more_to_read = false;
+        while socket.can_read() {
+            if incoming.len() == MAX_EVENTS_PER_LOCK {
+                more_to_read = true;
+                break;
             }
-            tungstenite::protocol::Message::Ping(data) => {
-                on_event(WsEvent::Message(WsMessage::Ping(data.into())))
+            let Some(event) = read_event(socket)? else {
+                break;
+            };
+            incoming.push(event);
+        }
+
+        let closed_by_server = !socket.can_read();
+        if closed_by_server {
+            // Send our answer to its close frame:
+            flush(socket).ok();
+        } else {
+            if let Some(keepalive) = &mut keepalive {
+                if !incoming.is_empty() {
+                    keepalive.on_receive();
+                }
+                if keepalive.check()? {
+                    write(
+                        socket,
+                        tungstenite::protocol::Message::Ping(Default::default()),
+                    )?;
+                }
             }
-            tungstenite::protocol::Message::Pong(data) => {
-                on_event(WsEvent::Message(WsMessage::Pong(data.into())))
+
+            // Once the socket becomes writable, we send whatever didn't fit before:
+            flush(socket)?;
+        }
+        drop(state);
+
+        for event in incoming.drain(..) {
+            if on_event(event).is_break() {
+                log::trace!("Closing connection due to Break");
+                if let Some(socket) = &mut shared.state.lock().socket {
+                    close(socket)?;
+                }
+                return Ok(ControlFlow::Break(()));
             }
-            tungstenite::protocol::Message::Close(close) => {
+        }
+
+        if closed_by_server {
+            return Ok(if close_deadline.is_some() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            });
+        }
+
+        if let Some(deadline) = close_deadline {
+            if deadline <= Instant::now() {
+                log::debug!(
+                    "Server did not answer our close frame within {:?}",
+                    options.close_timeout
+                );
                 #[expect(
                     unused_must_use,
                     reason = "we intentionally ignore the return of `on_event`"
                 )]
-                on_event(WsEvent::Closed);
-                log::debug!("WebSocket close received: {close:?}");
-                ControlFlow::Break(())
+                on_event(WsEvent::Closed(CloseInfo::abnormal()));
+                return Ok(ControlFlow::Break(()));
             }
-            tungstenite::protocol::Message::Frame(_) => ControlFlow::Continue(()),
-        },
-        // If we get `WouldBlock`, then the read timed out.
-        // Windows may emit `TimedOut` instead.
-        Err(tungstenite::Error::Io(io_err))
-            if io_err.kind() == std::io::ErrorKind::WouldBlock
-                || io_err.kind() == std::io::ErrorKind::TimedOut =>
-        {
-            ControlFlow::Continue(()) // Ignore
         }
-        Err(err) => {
-            return Err(format!("read: {err}"));
+
+        let deadline = [
+            keepalive.as_ref().map(KeepaliveTimer::next_deadline),
+            close_deadline,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if !more_to_read {
+            poller.wait(deadline)?;
         }
-    };
+    }
+}
 
-    Ok(control)
+/// Queue up a message to be sent.
+///
+/// The socket being full is not an error: the message is sent once it becomes writable.
+fn write(socket: &mut Socket, msg: tungstenite::protocol::Message) -> Result<()> {
+    match socket.write(msg) {
+        Err(err) if !is_would_block(&err) => Err(from_tungstenite(err)),
+        _ => Ok(()),
+    }
 }
 
-fn set_read_timeout(
-    s: &mut WebSocket<MaybeTlsStream<TcpStream>>,
-    value: Option<std::time::Duration>,
+/// Send as much of what we have queued up as the socket will take.
+fn flush(socket: &mut Socket) -> Result<()> {
+    match socket.flush() {
+        Err(err) if !is_would_block(&err) => Err(from_tungstenite(err)),
+        _ => Ok(()),
+    }
+}
+
+/// Start the closing handshake, without waiting for the reply.
+fn close(socket: &mut Socket) -> Result<()> {
+    match socket.close(None) {
+        Err(err) if !is_would_block(&err) => Err(from_tungstenite(err)),
+        _ => flush(socket),
+    }
+}
+
+fn is_would_block(err: &tungstenite::Error) -> bool {
+    matches!(err, tungstenite::Error::Io(err) if err.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+/// We have sent a close frame: wait for the server to answer with its own.
+fn wait_for_close_reply(
+    socket: &mut Socket,
+    timeout: Duration,
+    on_event: &EventHandler,
+    poller: &mut Poller,
 ) -> Result<()> {
-    // zero timeout is the same as no timeout
-    if value.is_none() || value.is_some_and(|value| value.is_zero()) {
-        return Ok(());
+    let deadline = Instant::now() + timeout;
+
+    while !read_close_reply(socket, deadline, on_event)? {
+        poller.wait(Some(deadline))?;
     }
+    Ok(())
+}
 
-    match s.get_mut() {
-        MaybeTlsStream::Plain(s) => {
-            s.set_read_timeout(value)
-                .map_err(|err| format!("failed to set read timeout: {err}"))?;
-        }
-        #[cfg(feature = "tls")]
-        MaybeTlsStream::Rustls(s) => {
-            s.get_mut()
-                .set_read_timeout(value)
-                .map_err(|err| format!("failed to set read timeout: {err}"))?;
+/// Read what there is to read while waiting for the answer to our close frame.
+///
+/// Returns `true` once we are done waiting, either because the server answered,
+/// or because the `deadline` has passed (which is reported as an abnormal closure).
+fn read_close_reply(
+    socket: &mut Socket,
+    deadline: Instant,
+    on_event: &EventHandler,
+) -> Result<bool> {
+    // Messages sent by the server before it saw our close frame are still delivered.
+    while let Some(control) = read_from_socket(socket, on_event)? {
+        if control.is_break() || !socket.can_read() {
+            return Ok(true);
         }
-        _ => {}
-    };
+    }
+    flush(socket)?;
 
-    Ok(())
+    if deadline <= Instant::now() {
+        log::debug!("Server did not answer our close frame in time");
+        #[expect(
+            unused_must_use,
+            reason = "we intentionally ignore the return of `on_event`"
+        )]
+        on_event(WsEvent::Closed(CloseInfo::abnormal()));
+        return Ok(true);
+    }
+    Ok(false)
 }
 
 #[test]
@@ -314,3 +1102,568 @@ fn test_connect() {
     let (mut sender, _receiver) = crate::connect("ws://example.com", options).unwrap();
     sender.send(crate::WsMessage::Text("Hello!".into()));
 }
+
+#[test]
+fn test_close_info() {
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        socket
+            .close(Some(tungstenite::protocol::CloseFrame {
+                code: tungstenite::protocol::frame::coding::CloseCode::Away,
+                reason: "shutting down".into(),
+            }))
+            .unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let (_sender, receiver) = crate::connect(url, crate::Options::default()).unwrap();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Closed(close)) => {
+                assert_eq!(
+                    close,
+                    CloseInfo {
+                        code: 1001,
+                        reason: "shutting down".to_owned(),
+                        was_clean: true,
+                    }
+                );
+                return;
+            }
+            Ok(WsEvent::Error(err)) => panic!("Unexpected error: {err}"),
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Closed: {err}"),
+        }
+    }
+}
+
+#[test]
+fn test_close_with() {
+    let (close_tx, close_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        loop {
+            // tungstenite answers the close frame for us
+            if let tungstenite::Message::Close(close) = socket.read().unwrap() {
+                socket.flush().ok();
+                close_tx.send(close).ok();
+                return;
+            }
+        }
+    });
+
+    let (mut sender, receiver) = crate::connect(url, crate::Options::default()).unwrap();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Opened(_)) => sender.close_with(4000, "bye"),
+            Ok(WsEvent::Closed(close)) => {
+                assert_eq!(close.code, 4000);
+                assert_eq!(close.reason, "bye");
+                assert!(close.was_clean, "The server answered our close frame");
+
+                let server_received = close_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+                assert_eq!(u16::from(server_received.unwrap().code), 4000);
+                return;
+            }
+            Ok(WsEvent::Error(err)) => panic!("Unexpected error: {err}"),
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Closed: {err}"),
+        }
+    }
+}
+
+#[test]
+fn test_reconnect() {
+    let mut num_accepted = 0;
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        num_accepted += 1;
+        if num_accepted == 1 {
+            // Drop the first connection right after the handshake:
+            drop(tungstenite::accept(stream).unwrap());
+        } else {
+            crate::tungstenite_common::echo(stream);
+        }
+    });
+
+    let options = crate::Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: std::time::Duration::from_millis(10),
+            jitter: std::time::Duration::ZERO,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (mut sender, receiver) = crate::connect(url, options).unwrap();
+
+    let mut num_opened = 0;
+    let mut num_reconnecting = 0;
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Opened(_)) => {
+                num_opened += 1;
+                if num_opened == 2 {
+                    sender.send(WsMessage::Text("hello".to_owned()));
+                }
+            }
+            Ok(WsEvent::Reconnecting { attempt, .. }) => {
+                assert_eq!(attempt, 1);
+                num_reconnecting += 1;
+            }
+            Ok(WsEvent::Message(WsMessage::Text(text))) => {
+                assert_eq!(text, "hello");
+                assert_eq!(num_opened, 2);
+                assert_eq!(num_reconnecting, 1);
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => panic!("No reconnection: {err}"),
+        }
+    }
+}
+
+#[test]
+fn test_close_while_reconnecting() {
+    check_close_while_reconnecting(crate::Options::default());
+    check_close_while_reconnecting(crate::Options {
+        split_threads: true,
+        ..Default::default()
+    });
+}
+
+/// Drop the [`WsSender`] while waiting to reconnect, and check that we don't.
+#[cfg(test)]
+fn check_close_while_reconnecting(options: crate::Options) {
+    let (accepted_tx, accepted_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        accepted_tx.send(()).ok();
+        // Drop the connection right after the handshake:
+        drop(tungstenite::accept(stream).unwrap());
+    });
+
+    let options = crate::Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: Duration::from_millis(200),
+            jitter: Duration::ZERO,
+            ..Default::default()
+        }),
+        ..options
+    };
+    let (sender, receiver) = crate::connect(url, options).unwrap();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Reconnecting { .. }) => break,
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Reconnecting: {err}"),
+        }
+    }
+    drop(sender);
+
+    accepted_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(
+        accepted_rx
+            .recv_timeout(Duration::from_millis(500))
+            .is_err(),
+        "Reconnected after the WsSender was dropped"
+    );
+    assert!(
+        !receiver
+            .iter()
+            .any(|event| matches!(event, WsEvent::Opened(_))),
+        "Opened again after the WsSender was dropped"
+    );
+}
+
+#[test]
+fn test_no_reconnect_after_close() {
+    check_no_reconnect_after_close(crate::Options::default());
+    check_no_reconnect_after_close(crate::Options {
+        split_threads: true,
+        ..Default::default()
+    });
+    check_no_reconnect_after_close(crate::Options {
+        shared_reactor: true,
+        ..Default::default()
+    });
+}
+
+/// Close the [`WsSender`], have the server answer with an invalid frame,
+/// and check that we don't reconnect.
+#[cfg(test)]
+fn check_no_reconnect_after_close(options: crate::Options) {
+    use std::io::{Read as _, Write as _};
+
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        // Wait for the start of the close frame, and then answer it with an invalid frame:
+        socket.get_mut().read_exact(&mut [0; 2]).ok();
+        socket.get_mut().write_all(&[0xff, 0x00]).ok();
+    });
+
+    let options = crate::Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            ..Default::default()
+        }),
+        ..options
+    };
+    let (mut sender, receiver) = crate::connect(url, options).unwrap();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Opened(_)) => sender.close_with(4000, "bye"),
+            Ok(WsEvent::Closed(_)) => break,
+            Ok(WsEvent::Reconnecting { .. }) => panic!("Reconnecting after close_with"),
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Closed: {err}"),
+        }
+    }
+    assert!(
+        !receiver
+            .iter()
+            .any(|event| matches!(event, WsEvent::Reconnecting { .. })),
+        "Reconnecting after close_with"
+    );
+}
+
+#[test]
+fn test_keepalive_timeout() {
+    let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        // Accept, but never read, so the pings go unanswered:
+        let _socket = tungstenite::accept(stream).unwrap();
+        done_rx.recv().ok();
+    });
+
+    let options = crate::Options {
+        keepalive: Some(crate::Keepalive {
+            interval: std::time::Duration::from_millis(50),
+            timeout: std::time::Duration::from_millis(100),
+        }),
+        ..Default::default()
+    };
+    let (_sender, receiver) = crate::connect(url, options).unwrap();
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Error(crate::Error::KeepaliveTimeout { .. })) => {
+                done_tx.send(()).ok();
+                return;
+            }
+            Ok(WsEvent::Error(err)) => panic!("Unexpected error: {err}"),
+            Ok(_) => {}
+            Err(err) => panic!("No keepalive timeout: {err}"),
+        }
+    }
+}
+
+#[test]
+fn test_open_info() {
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        let choose_protocol =
+            |_: &tungstenite::handshake::server::Request,
+             mut response: tungstenite::handshake::server::Response| {
+                response
+                    .headers_mut()
+                    .insert("sec-websocket-protocol", "v2".parse().unwrap());
+                Ok(response)
+            };
+        let mut socket = tungstenite::accept_hdr(stream, choose_protocol).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let options = crate::Options {
+        subprotocols: vec!["v1".to_owned(), "v2".to_owned()],
+        ..Default::default()
+    };
+    let (_sender, receiver) = crate::connect_blocking(url, options).unwrap();
+    let Some(WsEvent::Opened(open_info)) = receiver.try_recv() else {
+        panic!("Expected WsEvent::Opened");
+    };
+    assert_eq!(open_info.status, 101);
+    assert_eq!(open_info.subprotocol.as_deref(), Some("v2"));
+    assert_eq!(open_info.extensions, None);
+    assert!(open_info
+        .headers
+        .iter()
+        .any(|(name, value)| name == "upgrade" && value == "websocket"));
+}
+
+#[test]
+fn test_no_extensions() {
+    let (requested_tx, requested_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        let note_extensions =
+            |request: &tungstenite::handshake::server::Request,
+             response: tungstenite::handshake::server::Response| {
+                let extensions = request.headers().get("sec-websocket-extensions").cloned();
+                requested_tx.send(extensions).ok();
+                Ok(response)
+            };
+        let mut socket = tungstenite::accept_hdr(stream, note_extensions).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let (_sender, receiver) = crate::connect_blocking(url, crate::Options::default()).unwrap();
+    let Some(WsEvent::Opened(open_info)) = receiver.try_recv() else {
+        panic!("Expected WsEvent::Opened");
+    };
+    assert_eq!(open_info.extensions, None);
+
+    let requested = requested_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        requested, None,
+        "We can't do compression, so we shouldn't ask for it"
+    );
+}
+
+#[test]
+fn test_connect_timeout() {
+    // The connection is accepted by the OS, but nobody answers the upgrade request:
+    let (_listener, url) = crate::tungstenite_common::test_listener();
+
+    let options = crate::Options {
+        connect_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    match crate::connect_blocking(url, options) {
+        Err(Error::ConnectTimeout { timeout }) => {
+            assert_eq!(timeout, Duration::from_millis(200));
+        }
+        Err(err) => panic!("Unexpected error: {err}"),
+        Ok(_) => panic!("Expected a timeout"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_redirects() {
+    let (server_url, redirector_url) = crate::tungstenite_common::spawn_redirect_servers();
+    let connect = |redirects| {
+        let options = crate::Options {
+            redirects,
+            ..Default::default()
+        };
+        crate::connect_blocking(&redirector_url, options)
+    };
+
+    let (_sender, receiver) = connect(crate::RedirectPolicy::default()).unwrap();
+    match receiver.recv_timeout(Duration::from_secs(1)) {
+        Ok(WsEvent::Opened(open_info)) => assert_eq!(open_info.url, server_url),
+        event => panic!("Expected Opened, got {event:?}"),
+    }
+
+    let approve = crate::ApproveRedirect::new({
+        let redirector_url = redirector_url.clone();
+        move |from, to| {
+            assert_eq!((from, to), (redirector_url.as_str(), server_url.as_str()));
+            false
+        }
+    });
+    for redirects in [
+        crate::RedirectPolicy {
+            max_redirects: 0,
+            ..Default::default()
+        },
+        crate::RedirectPolicy {
+            allow_cross_origin: false,
+            ..Default::default()
+        },
+        crate::RedirectPolicy {
+            approve: Some(approve),
+            ..Default::default()
+        },
+    ] {
+        match connect(redirects.clone()) {
+            Err(Error::HandshakeRejected { status, .. }) => assert_eq!(status, 302),
+            Err(err) => panic!("Unexpected error: {err}"),
+            Ok(_) => panic!("{redirects:?} should not follow the redirect"),
+        }
+    }
+}
+
+#[test]
+fn test_handshake_rejected() {
+    use std::io::{BufRead as _, Write as _};
+
+    let url = crate::tungstenite_common::spawn_test_server(|mut stream| {
+        // Skip the request:
+        for line in std::io::BufReader::new(&stream).lines() {
+            if line.unwrap().is_empty() {
+                break;
+            }
+        }
+        stream
+            .write_all(
+                b"HTTP/1.1 401 Unauthorized\r\n\
+                WWW-Authenticate: Bearer\r\n\
+                Content-Length: 13\r\n\
+                \r\n\
+                token expired",
+            )
+            .unwrap();
+    });
+
+    match crate::connect_blocking(url, crate::Options::default()) {
+        Err(Error::HandshakeRejected {
+            status,
+            headers,
+            body,
+        }) => {
+            assert_eq!(status, 401);
+            assert!(headers
+                .iter()
+                .any(|(name, value)| name == "www-authenticate" && value == "Bearer"));
+            assert_eq!(body, b"token expired");
+        }
+        Err(err) => panic!("Unexpected error: {err}"),
+        Ok(_) => panic!("Expected the handshake to be rejected"),
+    }
+}
+
+#[test]
+fn test_send_throughput() {
+    const NUM_MESSAGES: usize = 500;
+
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        let mut num_received = 0;
+        while let Ok(msg) = socket.read() {
+            if msg.is_text() {
+                num_received += 1;
+                if num_received == NUM_MESSAGES {
+                    socket.send(tungstenite::Message::text("done")).ok();
+                }
+            }
+        }
+    });
+
+    // With one message per read timeout, this would take 5 seconds:
+    let (mut sender, receiver) = crate::connect_blocking(url, crate::Options::default()).unwrap();
+    for i in 0..NUM_MESSAGES {
+        sender.send(WsMessage::Text(i.to_string()));
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+    while std::time::Instant::now() < deadline {
+        match receiver.recv_timeout(std::time::Duration::from_millis(10)) {
+            Ok(WsEvent::Message(WsMessage::Text(text))) if text == "done" => return,
+            Ok(WsEvent::Error(err)) => panic!("Unexpected error: {err}"),
+            _ => {}
+        }
+    }
+    panic!("Timed out sending {NUM_MESSAGES} messages");
+}
+
+#[test]
+fn test_split_threads() {
+    let (received_tx, received_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        socket.send(tungstenite::Message::text("slow")).unwrap();
+        while let Ok(msg) = socket.read() {
+            if let tungstenite::Message::Text(text) = msg {
+                received_tx.send(text.to_string()).ok();
+            }
+        }
+    });
+
+    let (opened_tx, opened_rx) = std::sync::mpsc::channel();
+    let on_event: EventHandler = Box::new(move |event| {
+        match event {
+            WsEvent::Opened(_) => {
+                opened_tx.send(()).ok();
+            }
+            WsEvent::Message(_) => {
+                // A slow event handler:
+                std::thread::sleep(std::time::Duration::from_secs(2));
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    });
+    let options = crate::Options {
+        split_threads: true,
+        ..Default::default()
+    };
+    let mut sender = crate::ws_connect(url, options, on_event).unwrap();
+
+    opened_rx
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    // Give the event handler time to start handling the "slow" message:
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    sender.send(WsMessage::Text("hello".to_owned()));
+
+    let received = received_rx
+        .recv_timeout(std::time::Duration::from_secs(1))
+        .expect("Sending was held up by the event handler");
+    assert_eq!(received, "hello");
+}
+
+#[test]
+fn test_split_threads_flood() {
+    use std::io::Write as _;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let (received_tx, received_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        let reading_stream = stream.try_clone().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        let mut reading_socket = tungstenite::WebSocket::from_raw_socket(
+            reading_stream,
+            tungstenite::protocol::Role::Server,
+            None,
+        );
+
+        let received = Arc::new(AtomicBool::new(false));
+        std::thread::Builder::new()
+            .name("test_server_reader".to_owned())
+            .spawn({
+                let received = received.clone();
+                let received_tx = received_tx.clone();
+                move || {
+                    while let Ok(msg) = reading_socket.read() {
+                        if let tungstenite::Message::Text(text) = msg {
+                            received.store(true, Ordering::Relaxed);
+                            received_tx.send(text.to_string()).ok();
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        // Keep the reading thread of the client busy, with many small frames written at once:
+        let frames = [0x81, 5].iter().chain(b"flood").copied().cycle();
+        let frames: Vec<u8> = frames.take(7 * 10_000).collect();
+        while !received.load(Ordering::Relaxed) {
+            if socket.get_mut().write_all(&frames).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (flooded_tx, flooded_rx) = std::sync::mpsc::channel();
+    let num_received = std::sync::atomic::AtomicUsize::new(0);
+    let on_event: EventHandler = Box::new(move |event| {
+        if matches!(event, WsEvent::Message(_))
+            && num_received.fetch_add(1, Ordering::Relaxed) == 1000
+        {
+            flooded_tx.send(()).ok();
+        }
+        ControlFlow::Continue(())
+    });
+    let options = crate::Options {
+        split_threads: true,
+        ..Default::default()
+    };
+    let mut sender = crate::ws_connect(url, options, on_event).unwrap();
+
+    flooded_rx
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    sender.send(WsMessage::Text("hello".to_owned()));
+
+    let received = received_rx
+        .recv_timeout(std::time::Duration::from_secs(1))
+        .expect("Sending was held up by the incoming messages");
+    assert_eq!(received, "hello");
+}
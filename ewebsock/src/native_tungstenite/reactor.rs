@@ -0,0 +1,678 @@
+//! A single thread running many connections, see [`Options::shared_reactor`].
+//!
+//! The blocking handshake of each connection is done on a short-lived thread,
+//! after which the socket is handed over to the reactor thread.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::Instant,
+};
+
+use super::{close, connect, read_close_reply, step, Socket, Step, WsSender};
+use crate::tungstenite_common::KeepaliveTimer;
+use crate::{CloseInfo, Error, EventHandler, OpenInfo, Options, Result, WsEvent, WsMessage};
+
+/// Token for a [`WsSender`] or a handshake thread waking us up.
+const WAKER: mio::Token = mio::Token(usize::MAX);
+
+/// The id of the next connection, which is also the [`mio::Token`] of its socket.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The reactor thread, started by the first connection that uses it.
+static REACTOR: parking_lot::Mutex<Option<Handle>> = parking_lot::const_mutex(None);
+
+/// How to reach the reactor thread.
+#[derive(Clone)]
+struct Handle {
+    commands: Sender<Command>,
+    waker: Arc<mio::Waker>,
+
+    /// Where handshake threads register the sockets of new connections.
+    registry: Arc<mio::Registry>,
+}
+
+impl Handle {
+    /// Get the reactor thread, starting it if needed.
+    ///
+    /// The reactor thread forgets itself when it stops, so then a new one is started.
+    fn get() -> Result<Self> {
+        let mut reactor = REACTOR.lock();
+        if let Some(handle) = &*reactor {
+            return Ok(handle.clone());
+        }
+
+        let mut reactor_thread = Reactor::new()?;
+        let handle = reactor_thread.handle.clone();
+        std::thread::Builder::new()
+            .name("ewebsock-reactor".to_owned())
+            .spawn(move || reactor_thread.run())
+            .map_err(|err| Error::SpawnThread(Arc::new(err)))?;
+
+        *reactor = Some(handle.clone());
+        Ok(handle)
+    }
+
+    /// # Errors
+    /// If the reactor thread is gone.
+    fn send(&self, command: Command) -> Result<()> {
+        if self.commands.send(command).is_err() {
+            return Err(Error::SpawnThread(Arc::new(std::io::Error::other(
+                "The WebSocket reactor thread is gone",
+            ))));
+        }
+        if let Err(err) = self.waker.wake() {
+            log::warn!("Failed to wake up the WebSocket reactor thread: {err}");
+        }
+        Ok(())
+    }
+}
+
+enum Command {
+    /// Start a new connection.
+    Add(Box<Connection>),
+
+    /// A [`WsSender`] of this connection queued up a message, or was closed.
+    Wake { id: usize },
+
+    /// A handshake thread is done.
+    Connected {
+        id: usize,
+        result: Box<Result<(Socket, OpenInfo)>>,
+    },
+}
+
+pub(super) fn ws_receive_impl(url: String, options: Options, on_event: EventHandler) -> Result<()> {
+    Handle::get()?.send(Command::Add(Box::new(Connection::new(
+        url,
+        options,
+        on_event,
+        None,
+        Arc::new(AtomicBool::new(false)),
+    ))))
+}
+
+pub(super) fn ws_connect_impl(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> Result<WsSender> {
+    let handle = Handle::get()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    let connection = Connection::new(url, options, on_event, Some(rx), closed.clone());
+    let id = connection.id;
+    handle.send(Command::Add(Box::new(connection)))?;
+    Ok(WsSender {
+        tx: Some(tx),
+        waker: Some(super::Waker::Reactor(ConnectionWaker { id, handle })),
+        closed,
+    })
+}
+
+/// Wakes up the reactor thread to look at the connection of a [`WsSender`].
+pub(super) struct ConnectionWaker {
+    id: usize,
+    handle: Handle,
+}
+
+impl ConnectionWaker {
+    pub(super) fn wake(&self) {
+        if let Err(err) = self.handle.send(Command::Wake { id: self.id }) {
+            log::warn!("Failed to wake up the WebSocket reactor thread: {err}");
+        }
+    }
+}
+
+/// Runs on the reactor thread.
+struct Reactor {
+    poll: mio::Poll,
+    commands: Receiver<Command>,
+    handle: Handle,
+    connections: HashMap<usize, Connection>,
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        // Make the next connection start a new reactor thread, instead of talking to a dead one:
+        let mut reactor = REACTOR.lock();
+        if reactor
+            .as_ref()
+            .is_some_and(|handle| Arc::ptr_eq(&handle.waker, &self.handle.waker))
+        {
+            *reactor = None;
+        }
+    }
+}
+
+impl Reactor {
+    fn new() -> Result<Self> {
+        let poll = mio::Poll::new().map_err(|err| Error::Io(Arc::new(err)))?;
+        let waker =
+            mio::Waker::new(poll.registry(), WAKER).map_err(|err| Error::Io(Arc::new(err)))?;
+        let registry = poll
+            .registry()
+            .try_clone()
+            .map_err(|err| Error::Io(Arc::new(err)))?;
+        let (commands, commands_rx) = std::sync::mpsc::channel();
+        Ok(Self {
+            poll,
+            commands: commands_rx,
+            handle: Handle {
+                commands,
+                waker: Arc::new(waker),
+                registry: Arc::new(registry),
+            },
+            connections: HashMap::new(),
+        })
+    }
+
+    fn run(&mut self) {
+        let mut events = mio::Events::with_capacity(256);
+
+        // The connections to look at:
+        let mut ready = BTreeSet::new();
+
+        loop {
+            let deadline = self
+                .connections
+                .values()
+                .filter_map(Connection::deadline)
+                .min();
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(err) = self.poll.poll(&mut events, timeout) {
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    log::error!("WebSocket reactor failed to poll: {err}");
+                    self.fail_all(&Error::Io(Arc::new(err)));
+                    return;
+                }
+            }
+
+            for event in &events {
+                if event.token() == WAKER {
+                    self.handle_commands(&mut ready);
+                } else {
+                    ready.insert(event.token().0);
+                }
+            }
+
+            let now = Instant::now();
+            ready.extend(
+                self.connections
+                    .iter()
+                    .filter(|(_, connection)| {
+                        connection
+                            .deadline()
+                            .is_some_and(|deadline| deadline <= now)
+                    })
+                    .map(|(id, _)| *id),
+            );
+
+            while let Some(id) = ready.pop_first() {
+                if let Some(connection) = self.connections.get_mut(&id) {
+                    if connection.advance(&self.handle).is_break() {
+                        self.connections.remove(&id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// We can't go on, so tell all connections, including those still waiting to be added.
+    fn fail_all(&mut self, err: &Error) {
+        while let Ok(command) = self.commands.try_recv() {
+            if let Command::Add(connection) = command {
+                self.connections.insert(connection.id, *connection);
+            }
+        }
+        self.connections.retain(|_, connection| {
+            if (connection.on_event)(WsEvent::Error(err.clone())).is_continue() {
+                #[expect(
+                    unused_must_use,
+                    reason = "we intentionally ignore the return of `on_event`"
+                )]
+                (connection.on_event)(WsEvent::Closed(CloseInfo::abnormal()));
+            }
+            false
+        });
+    }
+
+    /// Handle everything sent to us, adding the connections to look at to `ready`.
+    fn handle_commands(&mut self, ready: &mut BTreeSet<usize>) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(mut connection) => {
+                    if connection.start_connecting(&self.handle).is_continue() {
+                        self.connections.insert(connection.id, *connection);
+                    }
+                }
+                Command::Wake { id } => {
+                    ready.insert(id);
+                }
+                Command::Connected { id, result } => {
+                    if let Some(connection) = self.connections.get_mut(&id) {
+                        if connection.on_connected(*result, &self.handle).is_break() {
+                            self.connections.remove(&id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One connection on the reactor thread, including its reconnections.
+struct Connection {
+    id: usize,
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+
+    /// `None` if we only receive.
+    rx: Option<Receiver<WsMessage>>,
+
+    /// Set once the [`WsSender`] is closed, after which we never reconnect.
+    sender_closed: Arc<AtomicBool>,
+
+    state: State,
+
+    /// Reconnection attempts since we were last connected.
+    attempt: u32,
+}
+
+enum State {
+    /// A handshake thread is connecting.
+    Connecting,
+
+    Open {
+        socket: Socket,
+        keepalive: Option<KeepaliveTimer>,
+
+        /// Set when there is more to read, but we gave the other connections a turn first.
+        read_again_at: Option<Instant>,
+    },
+
+    /// We sent a close frame, and are waiting for the server to answer with its own.
+    Closing { socket: Socket, deadline: Instant },
+
+    /// Waiting to reconnect.
+    Reconnecting { at: Instant },
+}
+
+impl Connection {
+    fn new(
+        url: String,
+        options: Options,
+        on_event: EventHandler,
+        rx: Option<Receiver<WsMessage>>,
+        sender_closed: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            url,
+            options,
+            on_event,
+            rx,
+            sender_closed,
+            state: State::Connecting,
+            attempt: 0,
+        }
+    }
+
+    /// When [`Self::advance`] should be called, even if nothing happens.
+    fn deadline(&self) -> Option<Instant> {
+        match &self.state {
+            State::Connecting => None,
+            State::Open {
+                keepalive,
+                read_again_at,
+                ..
+            } => read_again_at.or_else(|| keepalive.as_ref().map(KeepaliveTimer::next_deadline)),
+            State::Closing { deadline, .. } => Some(*deadline),
+            State::Reconnecting { at } => Some(*at),
+        }
+    }
+
+    /// Connect on a new thread, which reports back with [`Command::Connected`].
+    ///
+    /// Returns [`ControlFlow::Break`] if we are done with this connection.
+    fn start_connecting(&mut self, handle: &Handle) -> ControlFlow<()> {
+        self.state = State::Connecting;
+
+        let id = self.id;
+        let url = self.url.clone();
+        let options = self.options.clone();
+        let spawned = std::thread::Builder::new()
+            .name("ewebsock-connect".to_owned())
+            .spawn({
+                let handle = handle.clone();
+                move || {
+                    let result = connect(&url, &options, &handle.registry, mio::Token(id));
+                    let connected = handle.send(Command::Connected {
+                        id,
+                        result: Box::new(result),
+                    });
+                    if let Err(err) = connected {
+                        log::debug!("Dropping connection: {err:?}");
+                    }
+                }
+            });
+
+        match spawned {
+            Ok(_) => ControlFlow::Continue(()),
+            Err(err) => self.on_error(Error::SpawnThread(Arc::new(err))),
+        }
+    }
+
+    /// Returns [`ControlFlow::Break`] if we are done with this connection.
+    fn on_connected(
+        &mut self,
+        result: Result<(Socket, OpenInfo)>,
+        handle: &Handle,
+    ) -> ControlFlow<()> {
+        match result {
+            Ok((mut socket, open_info)) => {
+                if self.sender_closed.load(Ordering::Acquire) {
+                    log::debug!("WsSender closed while connecting.");
+                    close(&mut socket).ok();
+                    return ControlFlow::Break(());
+                }
+                self.attempt = 0;
+                if (self.on_event)(WsEvent::Opened(open_info)).is_break() {
+                    log::trace!("Closing connection due to Break");
+                    close(&mut socket).ok();
+                    return ControlFlow::Break(());
+                }
+                self.state = State::Open {
+                    socket,
+                    keepalive: self.options.keepalive.clone().map(KeepaliveTimer::new),
+                    read_again_at: None,
+                };
+                self.advance(handle)
+            }
+            Err(err) => self.on_error(err),
+        }
+    }
+
+    /// Do whatever can be done without blocking.
+    ///
+    /// Returns [`ControlFlow::Break`] if we are done with this connection.
+    fn advance(&mut self, handle: &Handle) -> ControlFlow<()> {
+        let result = match &mut self.state {
+            State::Connecting => return ControlFlow::Continue(()),
+            State::Reconnecting { at } => {
+                if self.sender_closed.load(Ordering::Acquire) {
+                    log::debug!("WsSender closed while waiting to reconnect.");
+                    return ControlFlow::Break(());
+                }
+                if *at <= Instant::now() {
+                    return self.start_connecting(handle);
+                }
+                return ControlFlow::Continue(());
+            }
+            State::Open {
+                socket,
+                keepalive,
+                read_again_at,
+            } => {
+                *read_again_at = None;
+                step(socket, keepalive.as_mut(), self.rx.as_ref(), &self.on_event)
+            }
+            State::Closing { socket, deadline } => {
+                read_close_reply(socket, *deadline, &self.on_event).map(|done| {
+                    if done {
+                        Step::Closed(ControlFlow::Break(()))
+                    } else {
+                        Step::Open
+                    }
+                })
+            }
+        };
+
+        match result {
+            Ok(Step::Open) => ControlFlow::Continue(()),
+            Ok(Step::Busy) => {
+                if let State::Open { read_again_at, .. } = &mut self.state {
+                    *read_again_at = Some(Instant::now());
+                }
+                ControlFlow::Continue(())
+            }
+            Ok(Step::SentClose) => {
+                let State::Open { socket, .. } =
+                    std::mem::replace(&mut self.state, State::Connecting)
+                else {
+                    unreachable!("Only an open connection sends a close frame");
+                };
+                self.state = State::Closing {
+                    socket,
+                    deadline: Instant::now() + self.options.close_timeout,
+                };
+                self.advance(handle)
+            }
+            Ok(Step::Closed(ControlFlow::Break(()))) => {
+                log::debug!("WebSocket connection closed.");
+                ControlFlow::Break(())
+            }
+            Ok(Step::Closed(ControlFlow::Continue(()))) => {
+                log::debug!("WebSocket connection closed by the server.");
+                self.reconnect_later()
+            }
+            Err(err) => self.on_error(err),
+        }
+    }
+
+    /// Report the error, and reconnect later if we should.
+    fn on_error(&mut self, err: Error) -> ControlFlow<()> {
+        (self.on_event)(WsEvent::Error(err))?;
+        (self.on_event)(WsEvent::Closed(CloseInfo::abnormal()))?;
+        self.reconnect_later()
+    }
+
+    /// Reconnect according to [`Options::reconnect`], if at all.
+    fn reconnect_later(&mut self) -> ControlFlow<()> {
+        if self.sender_closed.load(Ordering::Acquire) {
+            log::debug!("WsSender closed - not reconnecting.");
+            return ControlFlow::Break(());
+        }
+        let Some(reconnect) = &self.options.reconnect else {
+            return ControlFlow::Break(());
+        };
+        self.attempt += 1;
+        let Some(delay) = reconnect.delay(self.attempt) else {
+            log::debug!("Giving up after {} reconnection attempts", self.attempt - 1);
+            return ControlFlow::Break(());
+        };
+        (self.on_event)(WsEvent::Reconnecting {
+            attempt: self.attempt,
+            delay,
+        })?;
+        self.state = State::Reconnecting {
+            at: Instant::now() + delay,
+        };
+        ControlFlow::Continue(())
+    }
+}
+
+#[test]
+fn test_shared_reactor() {
+    const NUM_CONNECTIONS: usize = 20;
+
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        std::thread::Builder::new()
+            .name("test_echo".to_owned())
+            .spawn(move || crate::tungstenite_common::echo(stream))
+            .unwrap();
+    });
+
+    let options = Options {
+        shared_reactor: true,
+        ..Default::default()
+    };
+    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let mut senders = Vec::new();
+    for i in 0..NUM_CONNECTIONS {
+        let event_tx = event_tx.clone();
+        let on_event: EventHandler = Box::new(move |event| {
+            event_tx.send((i, event)).ok();
+            ControlFlow::Continue(())
+        });
+        senders.push(crate::ws_connect(url.clone(), options.clone(), on_event).unwrap());
+    }
+
+    let mut echoed = [false; NUM_CONNECTIONS];
+    while echoed.contains(&false) {
+        let (i, event) = event_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("Timed out waiting for the echoes");
+        match event {
+            WsEvent::Opened(_) => {
+                if let Some(sender) = senders.get_mut(i) {
+                    sender.send(WsMessage::Text(format!("hello {i}")));
+                }
+            }
+            WsEvent::Message(WsMessage::Text(text)) => {
+                assert_eq!(text, format!("hello {i}"));
+                if let Some(echoed) = echoed.get_mut(i) {
+                    *echoed = true;
+                }
+            }
+            WsEvent::Error(err) => panic!("Unexpected error: {err}"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_flood_on_reactor() {
+    use std::io::Write as _;
+
+    let stop_flooding = Arc::new(AtomicBool::new(false));
+    let flood_url = crate::tungstenite_common::spawn_test_server({
+        let stop_flooding = stop_flooding.clone();
+        move |stream| {
+            let mut socket = tungstenite::accept(stream).unwrap();
+            // Many small frames, written at once:
+            let frames = [0x81, 5].iter().chain(b"flood").copied().cycle();
+            let frames: Vec<u8> = frames.take(7 * 10_000).collect();
+            while !stop_flooding.load(Ordering::Relaxed) {
+                if socket.get_mut().write_all(&frames).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    let quiet_url = crate::tungstenite_common::spawn_test_server(crate::tungstenite_common::echo);
+
+    let options = Options {
+        shared_reactor: true,
+        ..Default::default()
+    };
+
+    let (flooded_tx, flooded_rx) = std::sync::mpsc::channel();
+    let num_received = AtomicUsize::new(0);
+    let on_flood: EventHandler = Box::new(move |event| {
+        if matches!(event, WsEvent::Message(_))
+            && num_received.fetch_add(1, Ordering::Relaxed) == 1000
+        {
+            flooded_tx.send(()).ok();
+        }
+        ControlFlow::Continue(())
+    });
+    let _flood_sender = crate::ws_connect(flood_url, options.clone(), on_flood).unwrap();
+    flooded_rx
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+
+    // The flood keeps going while the quiet connection opens, sends and receives:
+    let (mut quiet_sender, quiet_receiver) = crate::connect(quiet_url, options).unwrap();
+    let timeout = std::time::Duration::from_secs(2);
+    assert!(matches!(
+        quiet_receiver.recv_timeout(timeout),
+        Ok(WsEvent::Opened(_))
+    ));
+    quiet_sender.send(WsMessage::Text("hello".to_owned()));
+    assert!(matches!(
+        quiet_receiver.recv_timeout(timeout),
+        Ok(WsEvent::Message(WsMessage::Text(text))) if text == "hello"
+    ));
+
+    stop_flooding.store(true, Ordering::Relaxed);
+}
+
+#[test]
+fn test_close_while_reconnecting_on_reactor() {
+    super::check_close_while_reconnecting(Options {
+        shared_reactor: true,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_close_while_connecting_on_reactor() {
+    let (accept_tx, accept_rx) = std::sync::mpsc::channel::<()>();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        // Hold up the handshake until the WsSender is closed:
+        accept_rx.recv().ok();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let options = Options {
+        shared_reactor: true,
+        ..Default::default()
+    };
+    let (sender, receiver) = crate::connect(url, options).unwrap();
+    drop(sender);
+    accept_tx.send(()).unwrap();
+
+    assert!(
+        !receiver
+            .iter()
+            .any(|event| matches!(event, WsEvent::Opened(_))),
+        "Opened after the WsSender was dropped"
+    );
+}
+
+#[test]
+fn test_reactor_failure() {
+    let mut reactor = Reactor::new().unwrap();
+    let (receiver, on_event) = crate::WsReceiver::new();
+    reactor
+        .handle
+        .send(Command::Add(Box::new(Connection::new(
+            "ws://127.0.0.1:1".to_owned(),
+            Options::default(),
+            on_event,
+            None,
+            Arc::new(AtomicBool::new(false)),
+        ))))
+        .unwrap();
+
+    reactor.fail_all(&Error::Io(Arc::new(std::io::Error::other("poll failed"))));
+    assert!(matches!(
+        receiver.try_recv(),
+        Some(WsEvent::Error(Error::Io(_)))
+    ));
+    assert!(matches!(receiver.try_recv(), Some(WsEvent::Closed(_))));
+
+    let handle = reactor.handle.clone();
+    drop(reactor);
+    assert!(
+        handle
+            .send(Command::Add(Box::new(Connection::new(
+                "ws://127.0.0.1:1".to_owned(),
+                Options::default(),
+                Box::new(|_| ControlFlow::Continue(())),
+                None,
+                Arc::new(AtomicBool::new(false)),
+            ))))
+            .is_err(),
+        "The reactor thread is gone"
+    );
+}
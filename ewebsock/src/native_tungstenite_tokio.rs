@@ -1,13 +1,49 @@
-use std::ops::ControlFlow;
+use std::{
+    future::Future,
+    ops::ControlFlow,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
 
-use crate::tungstenite_common::into_requester;
-use crate::{EventHandler, Options, Result, WsEvent, WsMessage};
+use tokio::sync::{
+    mpsc::error::{SendError, TrySendError},
+    AcquireError, OwnedSemaphorePermit, Semaphore, TryAcquireError,
+};
+
+use crate::tungstenite_common::{
+    close_info, connect_error, from_tungstenite, into_requester, into_tungstenite_message,
+    open_info, parse_uri, redirect_target, KeepaliveTimer,
+};
+use crate::{CloseInfo, EventHandler, OpenInfo, Options, Result, WsEvent, WsMessage, WsReceiver};
+
+/// A message waiting to be sent, holding on to its slot in the send queue, if it got one.
+type QueuedMessage = (WsMessage, Option<OwnedSemaphorePermit>);
+
+/// Waiting for a free slot in the send queue.
+type AcquirePermit = Pin<
+    Box<dyn Future<Output = std::result::Result<OwnedSemaphorePermit, AcquireError>> + Send + Sync>,
+>;
 
 /// This is how you send [`WsMessage`]s to the server.
 ///
+/// Messages are sent in the order they were queued up.
+///
 /// When this is dropped, the connection is closed.
 pub struct WsSender {
-    tx: Option<tokio::sync::mpsc::Sender<WsMessage>>,
+    tx: Option<tokio::sync::mpsc::UnboundedSender<QueuedMessage>>,
+
+    /// One permit per free slot in the send queue, see [`Options::send_queue_capacity`].
+    capacity: Arc<Semaphore>,
+
+    /// The slot reserved by [`Self::poll_reserve`], for the next [`Self::send_reserved`].
+    reserved: Option<OwnedSemaphorePermit>,
+
+    /// Set while [`Self::poll_reserve`] is waiting for a free slot.
+    acquiring: Option<AcquirePermit>,
 }
 
 impl Drop for WsSender {
@@ -19,14 +55,102 @@ impl Drop for WsSender {
 impl WsSender {
     /// Send a message.
     ///
+    /// This ignores [`Options::send_queue_capacity`]: it never blocks, and never drops the message,
+    /// so the queue grows beyond the capacity if you keep sending faster than the server reads.
+    /// Use [`Self::try_send`], [`Self::send_async`] or the `Sink` to respect the capacity.
+    ///
     /// You have to wait for [`WsEvent::Opened`] before you can start sending messages.
     #[allow(clippy::needless_pass_by_ref_mut)]
     pub fn send(&mut self, msg: WsMessage) {
-        if let Some(tx) = self.tx.clone() {
-            tokio::spawn(async move { tx.send(msg).await });
+        if let Some(tx) = &self.tx {
+            let permit = self.capacity.clone().try_acquire_owned().ok();
+            if tx.send((msg, permit)).is_err() {
+                log::debug!("Ignoring message sent after the connection was closed");
+            }
         }
     }
 
+    /// Send a message, unless the queue is full.
+    ///
+    /// # Errors
+    /// * [`TrySendError::Full`] if [`Options::send_queue_capacity`] messages are already queued up.
+    /// * [`TrySendError::Closed`] if the connection is closed.
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub fn try_send(&mut self, msg: WsMessage) -> std::result::Result<(), TrySendError<WsMessage>> {
+        let Some(tx) = &self.tx else {
+            return Err(TrySendError::Closed(msg));
+        };
+        let permit = match self.capacity.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(TryAcquireError::NoPermits) => return Err(TrySendError::Full(msg)),
+            Err(TryAcquireError::Closed) => return Err(TrySendError::Closed(msg)),
+        };
+        tx.send((msg, Some(permit)))
+            .map_err(|SendError((msg, _))| TrySendError::Closed(msg))
+    }
+
+    /// Send a message, first waiting for room in the queue if it is full.
+    ///
+    /// # Errors
+    /// If the connection is closed.
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub async fn send_async(
+        &mut self,
+        msg: WsMessage,
+    ) -> std::result::Result<(), SendError<WsMessage>> {
+        let Some(tx) = &self.tx else {
+            return Err(SendError(msg));
+        };
+        let Ok(permit) = self.capacity.clone().acquire_owned().await else {
+            return Err(SendError(msg));
+        };
+        tx.send((msg, Some(permit)))
+            .map_err(|SendError((msg, _))| SendError(msg))
+    }
+
+    /// Wait for a free slot in the queue, for `Sink::poll_ready`.
+    ///
+    /// If the connection is closed, this is ready right away,
+    /// and [`Self::send_reserved`] ignores the message, like [`Self::send`].
+    pub(crate) fn poll_reserve(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.reserved.is_some() || self.tx.is_none() {
+            return Poll::Ready(());
+        }
+        let acquiring = self
+            .acquiring
+            .get_or_insert_with(|| Box::pin(self.capacity.clone().acquire_owned()));
+        let permit = std::task::ready!(acquiring.as_mut().poll(cx));
+        self.acquiring = None;
+        self.reserved = permit.ok();
+        Poll::Ready(())
+    }
+
+    /// Queue up a message in the slot reserved by [`Self::poll_reserve`], for `Sink::start_send`.
+    pub(crate) fn send_reserved(&mut self, msg: WsMessage) {
+        let Some(permit) = self.reserved.take() else {
+            self.send(msg);
+            return;
+        };
+        if let Some(tx) = &self.tx {
+            if tx.send((msg, Some(permit))).is_err() {
+                log::debug!("Ignoring message sent after the connection was closed");
+            }
+        }
+    }
+
+    /// Close the connection with the given close code and reason.
+    ///
+    /// The server is expected to answer with its own close frame, after which
+    /// [`WsEvent::Closed`] is reported. If it doesn't answer within
+    /// [`Options::close_timeout`], the closure is reported as abnormal (code `1006`).
+    pub fn close_with(&mut self, code: u16, reason: impl Into<String>) {
+        self.send(WsMessage::Close {
+            code,
+            reason: reason.into(),
+        });
+        self.close();
+    }
+
     /// Close the connection.
     ///
     /// This is called automatically when the sender is dropped.
@@ -44,127 +168,517 @@ impl WsSender {
     }
 }
 
+type WebSocketStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Connect, and then reconnect according to [`Options::reconnect`] whenever the connection is lost.
+///
+/// We never reconnect once the outgoing stream has ended or a close message was sent,
+/// not even if that happens while we wait to.
+///
+/// All errors are reported via `on_event`.
 async fn ws_connect_async(
     url: String,
     options: Options,
-    outgoing_messages_stream: impl futures::Stream<Item = WsMessage>,
-    on_event: EventHandler,
+    outgoing_messages_stream: impl futures::Stream<Item = QueuedMessage>,
+    mut on_event: EventHandler,
 ) {
-    use futures::StreamExt as _;
-    let uri: tungstenite::http::Uri = match url.parse() {
-        Ok(uri) => uri,
-        Err(err) => {
-            #[expect(
-                unused_must_use,
-                reason = "we intentionally ignore the return of `on_event`"
-            )]
-            on_event(WsEvent::Error(format!(
-                "Failed to parse URL {url:?}: {err}"
-            )));
+    use futures::{stream::FusedStream as _, FutureExt as _, StreamExt as _};
+
+    // Set once a close message was sent, so that we don't reconnect afterwards.
+    let sender_closed = AtomicBool::new(false);
+
+    // Messages sent while we are reconnecting stay in here until we are connected again.
+    let outgoing_messages_stream = outgoing_messages_stream
+        .inspect(|(outgoing_message, _)| {
+            if matches!(outgoing_message, WsMessage::Close { .. }) {
+                sender_closed.store(true, Ordering::Relaxed);
+            }
+        })
+        .fuse();
+    futures_util::pin_mut!(outgoing_messages_stream);
+
+    let mut attempt = 0;
+
+    // Messages we had to take out of the stream while waiting to reconnect,
+    // still holding on to their slots in the send queue.
+    let mut queued = Vec::new();
+
+    loop {
+        let result = match connect(&url, &options).await {
+            Ok((ws_stream, open_info)) => {
+                attempt = 0;
+                run_connection(
+                    ws_stream,
+                    open_info,
+                    &options,
+                    std::mem::take(&mut queued),
+                    &mut outgoing_messages_stream,
+                    &mut on_event,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(ControlFlow::Break(())) => {
+                log::debug!("WebSocket connection closed.");
+                return;
+            }
+            Ok(ControlFlow::Continue(())) => {
+                log::debug!("WebSocket connection closed by the server.");
+            }
+            Err(err) => {
+                if on_event(WsEvent::Error(err)).is_break()
+                    || on_event(WsEvent::Closed(CloseInfo::abnormal())).is_break()
+                {
+                    return;
+                }
+            }
+        }
+
+        // Take what was sent while the connection went down, to find out if the sender was closed:
+        while let Some(Some(outgoing_message)) = outgoing_messages_stream.next().now_or_never() {
+            queued.push(outgoing_message);
+        }
+        if sender_closed.load(Ordering::Relaxed) || outgoing_messages_stream.is_terminated() {
+            log::debug!("WsSender closed - not reconnecting.");
             return;
         }
+        let Some(reconnect) = &options.reconnect else {
+            return;
+        };
+        attempt += 1;
+        let Some(delay) = reconnect.delay(attempt) else {
+            log::debug!("Giving up after {} reconnection attempts", attempt - 1);
+            return;
+        };
+        if on_event(WsEvent::Reconnecting { attempt, delay }).is_break() {
+            return;
+        }
+
+        let reconnect_at = tokio::time::sleep(delay).fuse();
+        futures_util::pin_mut!(reconnect_at);
+        loop {
+            futures::select! {
+                () = reconnect_at => break,
+                outgoing_message = outgoing_messages_stream.next() => match outgoing_message {
+                    Some((WsMessage::Close { .. }, _)) | None => {
+                        log::debug!("WsSender closed while waiting to reconnect.");
+                        return;
+                    }
+                    Some(outgoing_message) => queued.push(outgoing_message),
+                },
+            }
+        }
+    }
+}
+
+/// Connect to the server and perform the WebSocket handshake, within [`Options::connect_timeout`].
+async fn connect(url: &str, options: &Options) -> Result<(WebSocketStream, OpenInfo)> {
+    match options.connect_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handshake(url, options))
+            .await
+            .unwrap_or(Err(crate::Error::ConnectTimeout { timeout })),
+        None => handshake(url, options).await,
+    }
+}
+
+/// Connect to the server and perform the WebSocket handshake,
+/// following redirects according to [`Options::redirects`].
+async fn handshake(url: &str, options: &Options) -> Result<(WebSocketStream, OpenInfo)> {
+    let mut uri = parse_uri(url)?;
+    let mut num_redirects = 0;
+    let (ws_stream, response) = loop {
+        match handshake_with(&uri, options).await {
+            Ok(result) => break result,
+            Err(tungstenite::Error::Http(response)) => {
+                let Some(target) =
+                    redirect_target(&uri, &response, &options.redirects, num_redirects)
+                else {
+                    return Err(connect_error(url, tungstenite::Error::Http(response)));
+                };
+                uri = target;
+                num_redirects += 1;
+            }
+            Err(err) => return Err(connect_error(url, err)),
+        }
     };
+
+    log::info!("WebSocket handshake has been successfully completed");
+
+    Ok((ws_stream, open_info(&uri, &response)))
+}
+
+/// Connect to the first address of `host` that works, with [`Options::tcp`] applied.
+async fn connect_direct(
+    host: &str,
+    port: u16,
+    tcp: &crate::TcpOptions,
+) -> std::io::Result<tokio::net::TcpStream> {
+    let mut last_err = None;
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        let socket = if addr.is_ipv4() {
+            tokio::net::TcpSocket::new_v4()?
+        } else {
+            tokio::net::TcpSocket::new_v6()?
+        };
+        crate::proxy::configure_socket(&socket2::SockRef::from(&socket), tcp)?;
+        match socket.connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| crate::proxy::not_resolved(host)))
+}
+
+/// Connect to `uri` and perform the WebSocket handshake, without following redirects.
+///
+/// `tokio_tungstenite` can neither connect through a proxy nor set [`Options::tcp`],
+/// so we open the TCP connection ourselves, and then do the handshake over it.
+async fn handshake_with(
+    uri: &tungstenite::http::Uri,
+    options: &Options,
+) -> tungstenite::Result<(WebSocketStream, tungstenite::handshake::client::Response)> {
     let config = tungstenite::protocol::WebSocketConfig::from(options.clone());
-    let disable_nagle = false; // God damn everyone who adds negations to the names of their variables
-    let (ws_stream, _response) = match tokio_tungstenite::connect_async_with_config(
-        into_requester(uri, options),
+    let request = into_requester(uri.clone(), options.clone());
+
+    #[cfg(not(any(
+        feature = "tls",
+        feature = "rustls-tls-native-roots",
+        feature = "native-tls"
+    )))]
+    let mode = tungstenite::client::uri_mode(uri)?;
+
+    let stream = if crate::proxy::proxy_for(options, uri)?.is_some() {
+        // The proxy handshake is blocking, so it gets its own thread:
+        let deadline = options
+            .connect_timeout
+            .map(|timeout| std::time::Instant::now() + timeout);
+        let (stream, watchdog) = tokio::task::spawn_blocking({
+            let options = options.clone();
+            let uri = uri.clone();
+            move || crate::proxy::connect_tcp(&options, &uri, deadline)
+        })
+        .await
+        .map_err(|err| tungstenite::Error::Io(std::io::Error::other(err)))??;
+        drop(watchdog); // From here on, `connect` times out with `tokio::time::timeout`
+        stream.set_nonblocking(true)?;
+        tokio::net::TcpStream::from_std(stream)?
+    } else {
+        let (host, port) = crate::proxy::host_and_port(uri)?;
+        connect_direct(host, port, &options.tcp).await?
+    };
+
+    #[cfg(any(
+        feature = "tls",
+        feature = "rustls-tls-native-roots",
+        feature = "native-tls"
+    ))]
+    return tokio_tungstenite::client_async_tls_with_config(
+        request,
+        stream,
         Some(config),
-        disable_nagle,
+        tls_connector(options)?,
     )
-    .await
-    {
-        Ok(result) => result,
-        Err(err) => {
-            #[expect(
-                unused_must_use,
-                reason = "we intentionally ignore the return of `on_event`"
-            )]
-            on_event(WsEvent::Error(err.to_string()));
-            return;
+    .await;
+
+    #[cfg(not(any(
+        feature = "tls",
+        feature = "rustls-tls-native-roots",
+        feature = "native-tls"
+    )))]
+    match mode {
+        tungstenite::stream::Mode::Plain => {
+            tokio_tungstenite::client_async_with_config(
+                request,
+                tokio_tungstenite::MaybeTlsStream::Plain(stream),
+                Some(config),
+            )
+            .await
         }
-    };
+        tungstenite::stream::Mode::Tls => Err(tungstenite::Error::Url(
+            tungstenite::error::UrlError::TlsFeatureNotEnabled,
+        )),
+    }
+}
 
-    log::info!("WebSocket handshake has been successfully completed");
+/// The connector for [`Options::tls`], or `None` to let `tokio_tungstenite` pick one.
+#[cfg(any(
+    feature = "tls",
+    feature = "rustls-tls-native-roots",
+    feature = "native-tls"
+))]
+fn tls_connector(options: &Options) -> tungstenite::Result<Option<tokio_tungstenite::Connector>> {
+    Ok(crate::tls::connector(&options.tls)?.map(Into::into))
+}
+
+/// Report [`WsEvent::Opened`], send the `queued` messages, and then send and receive messages
+/// until the connection is closed.
+///
+/// Returns [`ControlFlow::Continue`] if the server closed the connection,
+/// and [`ControlFlow::Break`] if we closed it.
+#[allow(clippy::needless_pass_by_ref_mut)] // `&EventHandler` is not `Send`, but `&mut EventHandler` is
+async fn run_connection(
+    mut ws_stream: WebSocketStream,
+    open_info: OpenInfo,
+    options: &Options,
+    queued: Vec<QueuedMessage>,
+    outgoing_messages_stream: &mut (impl futures::stream::FusedStream<Item = QueuedMessage> + Unpin),
+    on_event: &mut EventHandler,
+) -> Result<ControlFlow<()>> {
+    use futures::{FutureExt as _, SinkExt as _, StreamExt as _};
 
-    let control = on_event(WsEvent::Opened);
+    let control = on_event(WsEvent::Opened(open_info));
     if control.is_break() {
-        log::warn!("ControlFlow::Break not implemented for the tungstenite tokio backend");
+        log::trace!("Closing connection due to Break");
+        ws_stream.close(None).await.ok();
+        return Ok(ControlFlow::Break(()));
     }
 
-    let (write, read) = ws_stream.split();
+    for (outgoing_message, _permit) in queued {
+        ws_stream
+            .send(into_tungstenite_message(outgoing_message))
+            .await
+            .map_err(from_tungstenite)?;
+    }
 
-    let writer = outgoing_messages_stream
-        .map(|ws_message| match ws_message {
-            WsMessage::Text(text) => tungstenite::protocol::Message::Text(text.into()),
-            WsMessage::Binary(data) => tungstenite::protocol::Message::Binary(data.into()),
-            WsMessage::Ping(data) => tungstenite::protocol::Message::Ping(data.into()),
-            WsMessage::Pong(data) => tungstenite::protocol::Message::Pong(data.into()),
-            WsMessage::Unknown(_) => panic!("You cannot send WsMessage::Unknown"),
-        })
-        .map(Ok)
-        .forward(write);
-
-    let reader = read.for_each(move |event| {
-        let control = match event {
-            Ok(message) => match message {
-                tungstenite::protocol::Message::Text(text) => {
-                    on_event(WsEvent::Message(WsMessage::Text(text.to_string())))
+    let (mut write, read) = ws_stream.split();
+    let mut read = read.fuse();
+
+    // Set once we have sent a close frame, and are waiting for the server to answer it.
+    let mut close_deadline: Option<tokio::time::Instant> = None;
+
+    let mut keepalive = options.keepalive.clone().map(KeepaliveTimer::new);
+
+    loop {
+        let close_timed_out = async move {
+            match close_deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        }
+        .fuse();
+        futures_util::pin_mut!(close_timed_out);
+
+        let keepalive_deadline = keepalive.as_ref().map(KeepaliveTimer::next_deadline);
+        let keepalive_due = async move {
+            match keepalive_deadline {
+                Some(deadline) => {
+                    tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await;
                 }
-                tungstenite::protocol::Message::Binary(data) => {
-                    on_event(WsEvent::Message(WsMessage::Binary(data.into())))
+                None => futures::future::pending().await,
+            }
+        }
+        .fuse();
+        futures_util::pin_mut!(keepalive_due);
+
+        futures::select! {
+            event = read.next() => {
+                let Some(event) = event else {
+                    // The connection was closed
+                    return Ok(if close_deadline.is_some() {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    });
+                };
+                if let Some(keepalive) = &mut keepalive {
+                    keepalive.on_receive();
                 }
-                tungstenite::protocol::Message::Ping(data) => {
-                    on_event(WsEvent::Message(WsMessage::Ping(data.into())))
+                let control = match event {
+                    Ok(message) => match message {
+                        tungstenite::protocol::Message::Text(text) => {
+                            on_event(WsEvent::Message(WsMessage::Text(text.to_string())))
+                        }
+                        tungstenite::protocol::Message::Binary(data) => {
+                            on_event(WsEvent::Message(WsMessage::Binary(data.into())))
+                        }
+                        tungstenite::protocol::Message::Ping(data) => {
+                            on_event(WsEvent::Message(WsMessage::Ping(data.into())))
+                        }
+                        tungstenite::protocol::Message::Pong(data) => {
+                            on_event(WsEvent::Message(WsMessage::Pong(data.into())))
+                        }
+                        tungstenite::protocol::Message::Close(close) => {
+                            on_event(WsEvent::Closed(close_info(close)))
+                        }
+                        tungstenite::protocol::Message::Frame(_) => ControlFlow::Continue(()),
+                    },
+                    Err(err) => {
+                        return Err(from_tungstenite(err));
+                    }
+                };
+                if control.is_break() {
+                    log::trace!("Closing connection due to Break");
+                    write.close().await.ok(); // Sends a close frame
+                    return Ok(ControlFlow::Break(()));
                 }
-                tungstenite::protocol::Message::Pong(data) => {
-                    on_event(WsEvent::Message(WsMessage::Pong(data.into())))
+            }
+
+            outgoing_message = outgoing_messages_stream.next() => {
+                match outgoing_message {
+                    Some((outgoing_message, _permit)) => {
+                        if close_deadline.is_some() {
+                            log::debug!("Ignoring message sent after closing the connection");
+                            continue;
+                        }
+                        let is_close = matches!(outgoing_message, WsMessage::Close { .. });
+                        write.send(into_tungstenite_message(outgoing_message)).await.map_err(from_tungstenite)?;
+                        if is_close {
+                            close_deadline = Some(tokio::time::Instant::now() + options.close_timeout);
+                        }
+                    }
+                    None if close_deadline.is_some() => {
+                        // The sender was dropped by `close_with`; keep waiting for the server.
+                    }
+                    None => {
+                        write.close().await.ok();
+                        return Ok(ControlFlow::Break(()));
+                    }
                 }
-                tungstenite::protocol::Message::Close(_) => on_event(WsEvent::Closed),
-                tungstenite::protocol::Message::Frame(_) => ControlFlow::Continue(()),
-            },
-            Err(err) => on_event(WsEvent::Error(err.to_string())),
-        };
-        if control.is_break() {
-            log::warn!("ControlFlow::Break not implemented for the tungstenite tokio backend");
-        }
-        async {}
-    });
+            }
+
+            () = keepalive_due => {
+                if let Some(keepalive) = &mut keepalive {
+                    if keepalive.check()? {
+                        write.send(tungstenite::protocol::Message::Ping(Default::default())).await.map_err(from_tungstenite)?;
+                    }
+                }
+            }
 
-    futures_util::pin_mut!(reader, writer);
-    futures_util::future::select(reader, writer).await;
+            () = close_timed_out => {
+                log::debug!("Server did not answer our close frame within {:?}", options.close_timeout);
+                #[expect(
+                    unused_must_use,
+                    reason = "we intentionally ignore the return of `on_event`"
+                )]
+                on_event(WsEvent::Closed(CloseInfo::abnormal()));
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+    }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 pub(crate) fn ws_connect_impl(
     url: String,
     options: Options,
     on_event: EventHandler,
 ) -> Result<WsSender> {
-    Ok(ws_connect_native(url, options, on_event))
+    let handle = tokio::runtime::Handle::try_current()
+        .map_err(|err| crate::Error::SpawnThread(Arc::new(err)))?;
+    Ok(ws_connect_on(&handle, url, options, on_event))
+}
+
+/// Like [`crate::connect`], but runs the connection on the given tokio runtime.
+///
+/// Unlike [`crate::connect`], this can be called from outside of a tokio runtime,
+/// e.g. from the main thread of a GUI app.
+///
+/// Only available with the `tokio` feature.
+pub fn connect_on(
+    handle: &tokio::runtime::Handle,
+    url: impl Into<String>,
+    options: Options,
+) -> (WsSender, WsReceiver) {
+    let (receiver, on_event) = WsReceiver::new();
+    let sender = ws_connect_on(handle, url.into(), options, on_event);
+    (sender, receiver)
 }
 
-/// Like [`crate::ws_connect`], but cannot fail. Only available on native builds.
-fn ws_connect_native(url: String, options: Options, on_event: EventHandler) -> WsSender {
-    let (tx, mut rx) = tokio::sync::mpsc::channel(1000);
+/// Like [`crate::ws_connect`], but runs the connection on the given tokio runtime.
+///
+/// Unlike [`crate::ws_connect`], this can be called from outside of a tokio runtime,
+/// e.g. from the main thread of a GUI app.
+///
+/// Only available with the `tokio` feature.
+pub fn ws_connect_on(
+    handle: &tokio::runtime::Handle,
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> WsSender {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<QueuedMessage>();
+    let capacity = Arc::new(Semaphore::new(
+        options.send_queue_capacity.clamp(1, Semaphore::MAX_PERMITS),
+    ));
 
     let outgoing_messages_stream = async_stream::stream! {
-        while let Some(item) = rx.recv().await {
-            yield item;
+        while let Some(queued) = rx.recv().await {
+            yield queued; // The slot in the queue is freed once the message has been written
         }
         log::debug!("WsSender dropped - closing connection.");
     };
 
-    tokio::spawn(async move {
-        ws_connect_async(url.clone(), options, outgoing_messages_stream, on_event).await;
-        log::debug!("WS connection finished.");
-    });
-    WsSender { tx: Some(tx) }
+    {
+        let capacity = capacity.clone();
+        handle.spawn(async move {
+            ws_connect_async(url.clone(), options, outgoing_messages_stream, on_event).await;
+            log::debug!("WS connection finished.");
+            capacity.close(); // Wake up anyone waiting in `send_async`
+        });
+    }
+    WsSender {
+        tx: Some(tx),
+        capacity,
+        reserved: None,
+        acquiring: None,
+    }
 }
 
 pub(crate) fn ws_receive_impl(url: String, options: Options, on_event: EventHandler) -> Result<()> {
     ws_connect_impl(url, options, on_event).map(|sender| sender.forget())
 }
 
+/// Run `future` to completion on a new current-thread runtime.
+#[cfg(test)]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// A current-thread runtime running on a thread of its own,
+/// so that tests can block on events outside of it.
+#[cfg(test)]
+struct RuntimeThread {
+    handle: tokio::runtime::Handle,
+    shutdown_tx: tokio::sync::oneshot::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+#[cfg(test)]
+impl RuntimeThread {
+    fn spawn() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let handle = runtime.handle().clone();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .name("tokio".to_owned())
+            .spawn(move || {
+                runtime.block_on(shutdown_rx).ok();
+            })
+            .unwrap();
+        Self {
+            handle,
+            shutdown_tx,
+            thread,
+        }
+    }
+
+    /// Stop the runtime, and wait for its thread to finish.
+    fn shutdown(self) {
+        self.shutdown_tx.send(()).unwrap();
+        self.thread.join().unwrap();
+    }
+}
+
 #[cfg(feature = "tokio")]
 #[test]
 fn test_connect_tokio() {
@@ -179,3 +693,448 @@ fn test_connect_tokio() {
             sender.send(crate::WsMessage::Text("Hello!".into()));
         });
 }
+
+#[test]
+fn test_break_on_opened_tokio() {
+    let (closed_tx, closed_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        loop {
+            if socket.read().unwrap().is_close() {
+                closed_tx.send(()).ok();
+                return;
+            }
+        }
+    });
+
+    let num_events = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let on_event: EventHandler = {
+        let num_events = num_events.clone();
+        Box::new(move |_| {
+            num_events.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            ControlFlow::Break(())
+        })
+    };
+
+    block_on(ws_connect_async(
+        url,
+        Options::default(),
+        futures::stream::pending(),
+        on_event,
+    ));
+
+    closed_rx
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .expect("The server should get our close frame");
+    assert_eq!(num_events.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_connect_timeout_tokio() {
+    // The connection is accepted by the OS, but nobody answers the upgrade request:
+    let (_listener, url) = crate::tungstenite_common::test_listener();
+
+    let timeout = std::time::Duration::from_millis(200);
+    let options = Options {
+        connect_timeout: Some(timeout),
+        ..Default::default()
+    };
+    block_on(async {
+        assert!(matches!(
+            connect(&url, &options).await,
+            Err(crate::Error::ConnectTimeout { .. })
+        ));
+    });
+}
+
+#[test]
+fn test_redirects_tokio() {
+    let (server_url, redirector_url) = crate::tungstenite_common::spawn_redirect_servers();
+
+    block_on(async {
+        let (_ws_stream, open_info) = connect(&redirector_url, &Options::default()).await.unwrap();
+        assert_eq!(open_info.url, server_url);
+
+        let options = Options {
+            redirects: crate::RedirectPolicy {
+                allow_cross_origin: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            connect(&redirector_url, &options).await,
+            Err(crate::Error::HandshakeRejected { status: 302, .. })
+        ));
+    });
+}
+
+#[test]
+fn test_connect_through_proxy_tokio() {
+    use futures::{SinkExt as _, StreamExt as _};
+
+    let url = crate::tungstenite_common::spawn_test_server(crate::tungstenite_common::echo);
+
+    let proxy = crate::proxy::spawn_test_proxy();
+    block_on(async {
+        for proxy_url in [
+            format!("http://user:pass@{proxy}"),
+            format!("socks5://{proxy}"),
+            format!("socks5h://{proxy}"),
+        ] {
+            let options = Options {
+                proxy: crate::Proxy::Url(proxy_url.clone()),
+                ..Default::default()
+            };
+            let (mut ws_stream, _open_info) = connect(&url, &options).await.unwrap();
+            ws_stream.send("hello".into()).await.unwrap();
+            let echo = ws_stream.next().await.unwrap().unwrap();
+            assert_eq!(echo.into_text().unwrap(), "hello", "through {proxy_url}");
+            ws_stream.close(None).await.unwrap(); // Frees up the test server for the next one
+        }
+
+        let options = Options {
+            proxy: crate::Proxy::Url(format!("http://user:wrong@{proxy}")),
+            ..Default::default()
+        };
+        match connect(&url, &options).await {
+            Err(crate::Error::Connect(err)) => assert!(err.to_string().contains("407")),
+            Err(err) => panic!("Unexpected error: {err}"),
+            Ok(_) => panic!("Expected the proxy to refuse"),
+        }
+    });
+}
+
+#[test]
+fn test_close_while_reconnecting_tokio() {
+    use std::time::Duration;
+
+    let (accepted_tx, accepted_rx) = std::sync::mpsc::channel();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        accepted_tx.send(()).ok();
+        // Drop the connection right after the handshake:
+        drop(tungstenite::accept(stream).unwrap());
+    });
+
+    // Run the runtime on another thread, so that we can block here:
+    let runtime = RuntimeThread::spawn();
+
+    let options = Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: Duration::from_millis(200),
+            jitter: Duration::ZERO,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (sender, receiver) = connect_on(&runtime.handle, url, options);
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Reconnecting { .. }) => break,
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Reconnecting: {err}"),
+        }
+    }
+    drop(sender);
+
+    accepted_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(
+        accepted_rx
+            .recv_timeout(Duration::from_millis(500))
+            .is_err(),
+        "Reconnected after the WsSender was dropped"
+    );
+    assert!(
+        !receiver
+            .iter()
+            .any(|event| matches!(event, WsEvent::Opened(_))),
+        "Opened again after the WsSender was dropped"
+    );
+
+    runtime.shutdown();
+}
+
+#[test]
+fn test_no_reconnect_after_close_tokio() {
+    use std::io::{Read as _, Write as _};
+    use std::time::Duration;
+
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        // Wait for the start of the close frame, and then answer it with an invalid frame:
+        socket.get_mut().read_exact(&mut [0; 2]).ok();
+        socket.get_mut().write_all(&[0xff, 0x00]).ok();
+    });
+
+    // Run the runtime on another thread, so that we can block here:
+    let runtime = RuntimeThread::spawn();
+
+    let options = Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (mut sender, receiver) = connect_on(&runtime.handle, url, options);
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Opened(_)) => sender.close_with(4000, "bye"),
+            Ok(WsEvent::Closed(_)) => break,
+            Ok(WsEvent::Reconnecting { .. }) => panic!("Reconnecting after close_with"),
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Closed: {err}"),
+        }
+    }
+    assert!(
+        !receiver
+            .iter()
+            .any(|event| matches!(event, WsEvent::Reconnecting { .. })),
+        "Reconnecting after close_with"
+    );
+
+    runtime.shutdown();
+}
+
+#[test]
+fn test_send_queue_tokio() {
+    let url = crate::tungstenite_common::spawn_test_server(crate::tungstenite_common::echo);
+
+    block_on(async {
+        let options = Options {
+            send_queue_capacity: 2,
+            ..Default::default()
+        };
+        let (mut sender, receiver) = crate::connect(url, options).unwrap();
+
+        // The connection task hasn't had a chance to run yet:
+        sender.try_send(WsMessage::Text("0".to_owned())).unwrap();
+        sender.try_send(WsMessage::Text("1".to_owned())).unwrap();
+        assert!(matches!(
+            sender.try_send(WsMessage::Text("full".to_owned())),
+            Err(TrySendError::Full(_))
+        ));
+
+        for i in 2..10 {
+            sender
+                .send_async(WsMessage::Text(i.to_string()))
+                .await
+                .unwrap();
+        }
+
+        // Block on another thread, so the connection task can keep running on this one:
+        let received = tokio::task::spawn_blocking(move || {
+            let mut received = vec![];
+            while received.len() < 10 {
+                match receiver.recv_timeout(std::time::Duration::from_secs(5)) {
+                    Ok(WsEvent::Message(WsMessage::Text(text))) => received.push(text),
+                    Ok(_) => {}
+                    Err(err) => panic!("Received only {received:?}: {err}"),
+                }
+            }
+            received
+        })
+        .await
+        .unwrap();
+        let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        assert_eq!(received, expected);
+    });
+}
+
+#[test]
+fn test_send_queue_while_reconnecting_tokio() {
+    use std::time::Duration;
+
+    let url = crate::tungstenite_common::spawn_test_server(|stream| {
+        // Drop the connection right after the handshake:
+        drop(tungstenite::accept(stream).unwrap());
+    });
+
+    // Run the runtime on another thread, so that we can block here:
+    let runtime = RuntimeThread::spawn();
+
+    let options = Options {
+        reconnect: Some(crate::ReconnectPolicy {
+            initial_delay: Duration::from_secs(60),
+            jitter: Duration::ZERO,
+            ..Default::default()
+        }),
+        send_queue_capacity: 2,
+        ..Default::default()
+    };
+    let (mut sender, receiver) = connect_on(&runtime.handle, url, options);
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(WsEvent::Reconnecting { .. }) => break,
+            Ok(_) => {}
+            Err(err) => panic!("No WsEvent::Reconnecting: {err}"),
+        }
+    }
+
+    sender.try_send(WsMessage::Text("0".to_owned())).unwrap();
+    sender.try_send(WsMessage::Text("1".to_owned())).unwrap();
+
+    // Give the connection task time to take the messages out of the queue while it waits:
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(matches!(
+        sender.try_send(WsMessage::Text("full".to_owned())),
+        Err(TrySendError::Full(_))
+    ));
+
+    drop(sender);
+    runtime.shutdown();
+}
+
+#[test]
+fn test_keepalive_timeout_tokio() {
+    use std::time::{Duration, Instant};
+
+    let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+    let url = crate::tungstenite_common::spawn_test_server(move |stream| {
+        // Accept, but never read, so the pings go unanswered:
+        let _socket = tungstenite::accept(stream).unwrap();
+        done_rx.recv().ok();
+    });
+
+    let keepalive = crate::Keepalive {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(100),
+    };
+    let options = Options {
+        keepalive: Some(keepalive.clone()),
+        ..Default::default()
+    };
+    let elapsed = block_on(async {
+        let (_sender, receiver) = crate::connect(url, options).unwrap();
+
+        // Block on another thread, so the connection task can keep running on this one:
+        tokio::task::spawn_blocking(move || {
+            let mut opened_at = None;
+            loop {
+                match receiver.recv_timeout(Duration::from_secs(5)) {
+                    Ok(WsEvent::Opened(_)) => opened_at = Some(Instant::now()),
+                    Ok(WsEvent::Error(crate::Error::KeepaliveTimeout { .. })) => {
+                        return opened_at.expect("Opened before the timeout").elapsed();
+                    }
+                    Ok(WsEvent::Error(err)) => panic!("Unexpected error: {err}"),
+                    Ok(_) => {}
+                    Err(err) => panic!("No keepalive timeout: {err}"),
+                }
+            }
+        })
+        .await
+        .unwrap()
+    });
+    done_tx.send(()).ok();
+
+    // Some slack for slow CI machines:
+    let slack = Duration::from_millis(200);
+    assert!(
+        elapsed < keepalive.interval + keepalive.timeout + slack,
+        "The keepalive timeout took {elapsed:?}"
+    );
+}
+
+#[test]
+fn test_connect_blocking_current_thread() {
+    let (_listener, url) = crate::tungstenite_common::test_listener();
+
+    let result = block_on(async { crate::connect_blocking(url, Options::default()) });
+    assert!(
+        matches!(result, Err(crate::Error::BlockingOnCurrentThread)),
+        "Blocking would deadlock the runtime"
+    );
+}
+
+#[test]
+fn test_sink_backpressure_tokio() {
+    use futures::SinkExt;
+
+    // Nobody answers the handshake, so nothing ever leaves the queue:
+    let (_listener, url) = crate::tungstenite_common::test_listener();
+
+    block_on(async {
+        let options = Options {
+            send_queue_capacity: 2,
+            ..Default::default()
+        };
+        let (mut sender, _receiver) = crate::connect(url, options).unwrap();
+
+        for i in 0..2 {
+            SinkExt::send(&mut sender, WsMessage::Text(i.to_string()))
+                .await
+                .unwrap();
+        }
+        let full = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            SinkExt::send(&mut sender, WsMessage::Text("full".to_owned())),
+        )
+        .await;
+        assert!(full.is_err(), "The sink should wait for room in the queue");
+        assert!(matches!(
+            sender.try_send(WsMessage::Text("full".to_owned())),
+            Err(TrySendError::Full(_))
+        ));
+    });
+}
+
+#[test]
+fn test_connect_on() {
+    let url = crate::tungstenite_common::spawn_test_server(crate::tungstenite_common::echo);
+
+    // Run the runtime on another thread, so that we are not inside of it here:
+    let runtime = RuntimeThread::spawn();
+
+    assert!(crate::connect(url.clone(), Options::default()).is_err());
+
+    let (mut sender, receiver) = connect_on(&runtime.handle, url, Options::default());
+    sender.send(WsMessage::Text("hello".to_owned()));
+    let timeout = std::time::Duration::from_secs(5);
+    assert!(matches!(
+        receiver.recv_timeout(timeout),
+        Ok(WsEvent::Opened(_))
+    ));
+    assert!(matches!(
+        receiver.recv_timeout(timeout),
+        Ok(WsEvent::Message(WsMessage::Text(text))) if text == "hello"
+    ));
+
+    runtime.shutdown();
+}
+
+#[cfg(any(
+    feature = "tls",
+    feature = "rustls-tls-native-roots",
+    feature = "native-tls"
+))]
+#[test]
+fn test_tls_options_tokio() {
+    use crate::tls::test_certs;
+
+    test_certs::install_crypto_provider();
+    let url = test_certs::spawn_echo_server(true);
+    let options = Options {
+        tls: crate::TlsOptions {
+            extra_root_certificates_pem: vec![test_certs::CA_CERTIFICATE.to_owned()],
+            only_extra_root_certificates: true,
+            client_certificate: Some(crate::ClientCertificate {
+                certificate_chain_pem: test_certs::CLIENT_CERTIFICATE.to_owned(),
+                private_key_pem: test_certs::CLIENT_PRIVATE_KEY.to_owned(),
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    block_on(async {
+        assert!(
+            matches!(
+                connect(&url, &Options::default()).await,
+                Err(crate::Error::Tls(_))
+            ),
+            "The self-signed certificate should not be trusted by default"
+        );
+        connect(&url, &options).await.unwrap();
+    });
+}
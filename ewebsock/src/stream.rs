@@ -0,0 +1,136 @@
+//! [`futures_core::Stream`] and [`futures_sink::Sink`] support, behind the `futures` feature.
+
+use std::{
+    ops::ControlFlow,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{EventHandler, Options, Result, WsEvent, WsMessage, WsSender};
+
+/// An async stream of incoming [`WsEvent`]s.
+///
+/// Unlike [`crate::WsReceiver`], this can be awaited on,
+/// e.g. in a `select!` alongside other futures.
+///
+/// The stream ends when the connection is closed for good,
+/// i.e. after the last [`WsEvent::Closed`].
+pub struct WsEventStream {
+    rx: futures_channel::mpsc::UnboundedReceiver<WsEvent>,
+}
+
+impl WsEventStream {
+    /// Returns a stream and an event-handler that can be passed to `crate::ws_connect`.
+    pub fn new() -> (Self, EventHandler) {
+        let (tx, rx) = futures_channel::mpsc::unbounded();
+        let on_event = Box::new(move |event| {
+            if tx.unbounded_send(event).is_ok() {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+        (Self { rx }, on_event)
+    }
+}
+
+impl futures_core::Stream for WsEventStream {
+    type Item = WsEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WsEvent>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rx.size_hint()
+    }
+}
+
+impl futures_core::stream::FusedStream for WsEventStream {
+    fn is_terminated(&self) -> bool {
+        self.rx.is_terminated()
+    }
+}
+
+/// With the tokio backend, the sink is ready once there is room in the send queue
+/// (see [`Options::send_queue_capacity`]).
+/// Other backends never block on sending, so the sink is always ready.
+///
+/// Closing the sink closes the connection, like [`WsSender::close`].
+///
+/// Note that the inherent [`WsSender::send`] shadows `SinkExt::send`,
+/// so use `SinkExt::send(&mut sender, msg)` if you want the future.
+impl futures_sink::Sink<WsMessage> for WsSender {
+    type Error = crate::Error;
+
+    #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_reserve(cx).map(Ok)
+    }
+
+    #[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+    fn start_send(self: Pin<&mut Self>, msg: WsMessage) -> Result<()> {
+        self.get_mut().send_reserved(msg);
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+    fn start_send(self: Pin<&mut Self>, msg: WsMessage) -> Result<()> {
+        self.get_mut().send(msg);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Connect to the given URL, and return a [`futures_sink::Sink`] of outgoing messages
+/// and a [`futures_core::Stream`] of incoming events.
+///
+/// This works with any backend. On web, drive it with `wasm_bindgen_futures::spawn_local`.
+///
+/// Note that you have to wait for [`WsEvent::Opened`] before sending messages.
+///
+/// # Errors
+/// * On native: failure to spawn a thread.
+/// * On web: failure to use `WebSocket` API.
+pub fn connect_stream(
+    url: impl Into<String>,
+    options: Options,
+) -> Result<(WsSender, WsEventStream)> {
+    let (stream, on_event) = WsEventStream::new();
+    let sender = crate::ws_connect(url.into(), options, on_event)?;
+    Ok((sender, stream))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(not(feature = "tokio"))] // The tokio backend needs a tokio runtime
+#[test]
+fn test_stream_and_sink() {
+    use futures::{SinkExt, StreamExt as _};
+
+    let url = crate::tungstenite_common::spawn_test_server(crate::tungstenite_common::echo);
+
+    let (mut sink, mut stream) = connect_stream(url, Options::default()).unwrap();
+    futures::executor::block_on(async {
+        assert!(matches!(stream.next().await, Some(WsEvent::Opened(_))));
+        SinkExt::send(&mut sink, WsMessage::Text("hello".to_owned()))
+            .await
+            .unwrap();
+        match stream.next().await {
+            Some(WsEvent::Message(WsMessage::Text(text))) => assert_eq!(text, "hello"),
+            other => panic!("Unexpected event: {other:?}"),
+        }
+    });
+}
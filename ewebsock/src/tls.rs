@@ -0,0 +1,152 @@
+//! Custom TLS configuration, see [`crate::TlsOptions`].
+//!
+//! Shared by both native backends.
+
+#[cfg(feature = "native-tls")]
+mod native_tls_backend;
+
+#[cfg(feature = "native-tls")]
+pub use native_tls_backend::PinningNotSupported;
+
+#[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+mod rustls_backend;
+
+#[cfg(test)]
+pub(crate) mod test_certs;
+
+use crate::TlsOptions;
+
+/// What to set up TLS with, converted to the connector of the backend.
+pub enum Connector {
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::TlsConnector),
+
+    #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+    Rustls(std::sync::Arc<rustls::ClientConfig>),
+}
+
+/// The connector for [`TlsOptions`].
+///
+/// `None` for the default options, in which case `tungstenite` picks one:
+/// `native-tls` if enabled, otherwise `rustls`.
+/// Other options use `rustls` if enabled, since only it supports [`TlsOptions::pinned_public_keys`].
+///
+/// # Errors
+/// If the options can't be used, e.g. an invalid certificate.
+pub fn connector(options: &TlsOptions) -> tungstenite::Result<Option<Connector>> {
+    #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+    if let Some(config) =
+        rustls_backend::rustls_config(options).map_err(|err| tungstenite::Error::Tls(err.into()))?
+    {
+        return Ok(Some(Connector::Rustls(config)));
+    }
+
+    #[cfg(feature = "native-tls")]
+    if options != &TlsOptions::default() {
+        return native_tls_backend::connector(options)
+            .map(|connector| Some(Connector::NativeTls(connector)));
+    }
+
+    Ok(None)
+}
+
+impl From<Connector> for tungstenite::Connector {
+    fn from(connector: Connector) -> Self {
+        match connector {
+            #[cfg(feature = "native-tls")]
+            Connector::NativeTls(connector) => Self::NativeTls(connector),
+
+            #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+            Connector::Rustls(config) => Self::Rustls(config),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl From<Connector> for tokio_tungstenite::Connector {
+    fn from(connector: Connector) -> Self {
+        match connector {
+            #[cfg(feature = "native-tls")]
+            Connector::NativeTls(connector) => Self::NativeTls(connector),
+
+            #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+            Connector::Rustls(config) => Self::Rustls(config),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+#[test]
+fn test_tls_options() {
+    use crate::{ClientCertificate, Options};
+
+    test_certs::install_crypto_provider();
+
+    let echo = |url: &str, tls: TlsOptions| -> crate::Result<()> {
+        let options = Options {
+            tls,
+            ..Default::default()
+        };
+        let (mut sender, receiver) = crate::connect_blocking(url, options)?;
+        sender.send(crate::WsMessage::Text("hello".to_owned()));
+        loop {
+            match receiver.recv_timeout(std::time::Duration::from_secs(5)) {
+                Ok(crate::WsEvent::Message(crate::WsMessage::Text(text))) => {
+                    assert_eq!(text, "hello");
+                    return Ok(());
+                }
+                Ok(crate::WsEvent::Error(err)) => return Err(err),
+                Ok(_) => {}
+                Err(err) => panic!("No echo: {err}"),
+            }
+        }
+    };
+    let trusting_ca = TlsOptions {
+        extra_root_certificates_pem: vec![test_certs::CA_CERTIFICATE.to_owned()],
+        only_extra_root_certificates: true,
+        ..Default::default()
+    };
+
+    let url = test_certs::spawn_echo_server(false);
+    assert!(
+        matches!(echo(&url, TlsOptions::default()), Err(crate::Error::Tls(_))),
+        "The self-signed certificate should not be trusted by default"
+    );
+    echo(&url, trusting_ca.clone()).unwrap();
+
+    let pinned = |pin| TlsOptions {
+        pinned_public_keys: vec![pin],
+        ..trusting_ca.clone()
+    };
+    if cfg!(any(feature = "tls", feature = "rustls-tls-native-roots")) {
+        echo(&url, pinned(test_certs::SERVER_PUBLIC_KEY_SHA256)).unwrap();
+        assert!(
+            matches!(echo(&url, pinned([0; 32])), Err(crate::Error::Tls(_))),
+            "The pin doesn't match the server's public key"
+        );
+    } else {
+        let err = echo(&url, pinned(test_certs::SERVER_PUBLIC_KEY_SHA256)).unwrap_err();
+        assert!(matches!(err, crate::Error::Tls(_)), "{err:?}");
+        assert!(
+            std::error::Error::source(&err)
+                .unwrap()
+                .to_string()
+                .contains("not supported with the native-tls backend"),
+            "Pinning is not supported with native-tls"
+        );
+    }
+
+    let url = test_certs::spawn_echo_server(true);
+    assert!(
+        echo(&url, trusting_ca.clone()).is_err(),
+        "The server requires a client certificate"
+    );
+    let with_client_certificate = TlsOptions {
+        client_certificate: Some(ClientCertificate {
+            certificate_chain_pem: test_certs::CLIENT_CERTIFICATE.to_owned(),
+            private_key_pem: test_certs::CLIENT_PRIVATE_KEY.to_owned(),
+        }),
+        ..trusting_ca
+    };
+    echo(&url, with_client_certificate).unwrap();
+}
@@ -0,0 +1,50 @@
+//! [`TlsOptions`] with `native-tls`.
+
+use crate::TlsOptions;
+
+/// The `native-tls` connector for [`TlsOptions`].
+///
+/// # Errors
+/// If a certificate or key is invalid, or [`TlsOptions::pinned_public_keys`] is used,
+/// which `native-tls` can't check (see [`PinningNotSupported`]).
+pub fn connector(options: &TlsOptions) -> tungstenite::Result<native_tls::TlsConnector> {
+    if !options.pinned_public_keys.is_empty() {
+        return Err(tungstenite::Error::Io(std::io::Error::other(
+            PinningNotSupported,
+        )));
+    }
+
+    build_connector(options).map_err(|err| tungstenite::Error::Tls(err.into()))
+}
+
+/// [`TlsOptions::pinned_public_keys`] was set, but `native-tls` can't check it.
+#[derive(Debug)]
+pub struct PinningNotSupported;
+
+impl std::fmt::Display for PinningNotSupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Public-key pinning is not supported with the native-tls backend; enable the `tls` or `rustls-tls-native-roots` feature")
+    }
+}
+
+impl std::error::Error for PinningNotSupported {}
+
+fn build_connector(options: &TlsOptions) -> Result<native_tls::TlsConnector, native_tls::Error> {
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.disable_built_in_roots(options.only_extra_root_certificates);
+
+    for pem in &options.extra_root_certificates_pem {
+        for certificate in native_tls::Certificate::stack_from_pem(pem.as_bytes())? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let Some(client_certificate) = &options.client_certificate {
+        builder.identity(native_tls::Identity::from_pkcs8(
+            client_certificate.certificate_chain_pem.as_bytes(),
+            client_certificate.private_key_pem.as_bytes(),
+        )?);
+    }
+
+    builder.build()
+}
@@ -0,0 +1,192 @@
+//! [`TlsOptions`] with `rustls`.
+
+use std::sync::Arc;
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{pem::PemObject as _, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::TlsOptions;
+
+/// The `rustls` configuration for [`TlsOptions`].
+///
+/// `None` for the default options, in which case `tungstenite` picks the root certificates.
+///
+/// # Errors
+/// If a certificate or key is invalid, or no `rustls` crypto provider is installed.
+pub fn rustls_config(
+    options: &TlsOptions,
+) -> Result<Option<Arc<rustls::ClientConfig>>, rustls::Error> {
+    if let Some(config) = &options.rustls_config {
+        return Ok(Some(config.0.clone()));
+    }
+    if options == &TlsOptions::default() {
+        return Ok(None);
+    }
+
+    let provider = rustls::crypto::CryptoProvider::get_default()
+        .cloned()
+        .ok_or_else(|| {
+            rustls::Error::General(
+                "No rustls crypto provider installed, see CryptoProvider::install_default"
+                    .to_owned(),
+            )
+        })?;
+    let roots = Arc::new(root_certificates(options)?);
+
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = if options.pinned_public_keys.is_empty() {
+        builder.with_root_certificates(roots)
+    } else {
+        let verifier = rustls::client::WebPkiServerVerifier::builder_with_provider(roots, provider)
+            .build()
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedPublicKeys {
+                verifier,
+                pins: options.pinned_public_keys.clone(),
+            }))
+    };
+
+    let config = match &options.client_certificate {
+        Some(client_certificate) => {
+            let chain = parse_certificates(&client_certificate.certificate_chain_pem)?;
+            let key = PrivateKeyDer::from_pem_slice(client_certificate.private_key_pem.as_bytes())
+                .map_err(|err| rustls::Error::General(format!("Invalid private key: {err}")))?;
+            builder.with_client_auth_cert(chain, key)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Some(Arc::new(config)))
+}
+
+fn root_certificates(options: &TlsOptions) -> Result<RootCertStore, rustls::Error> {
+    let mut roots = RootCertStore::empty();
+
+    if !options.only_extra_root_certificates {
+        #[cfg(feature = "tls")]
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        #[cfg(feature = "rustls-tls-native-roots")]
+        {
+            let native = rustls_native_certs::load_native_certs();
+            if !native.errors.is_empty() {
+                log::warn!(
+                    "Failed to load some native root certificates: {:?}",
+                    native.errors
+                );
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+
+    for pem in &options.extra_root_certificates_pem {
+        for certificate in parse_certificates(pem)? {
+            roots.add(certificate)?;
+        }
+    }
+
+    Ok(roots)
+}
+
+fn parse_certificates(pem: &str) -> Result<Vec<CertificateDer<'static>>, rustls::Error> {
+    let certificates = CertificateDer::pem_slice_iter(pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| rustls::Error::General(format!("Invalid certificate: {err}")))?;
+    if certificates.is_empty() {
+        return Err(rustls::Error::General(
+            "No certificate found in PEM".to_owned(),
+        ));
+    }
+    Ok(certificates)
+}
+
+/// Verifies the certificate as usual, and then checks its public key against [`TlsOptions::pinned_public_keys`].
+#[derive(Debug)]
+struct PinnedPublicKeys {
+    verifier: Arc<rustls::client::WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedPublicKeys {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        if self.pins.contains(&public_key_sha256(end_entity)?) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+/// The SHA-256 hash of the DER-encoded `SubjectPublicKeyInfo` of `certificate`,
+/// as in [`TlsOptions::pinned_public_keys`].
+fn public_key_sha256(certificate: &CertificateDer<'_>) -> Result<[u8; 32], rustls::Error> {
+    let certificate = webpki::EndEntityCert::try_from(certificate)
+        .map_err(|_err| rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding))?;
+    let public_key = certificate.subject_public_key_info();
+    Ok(<sha2::Sha256 as sha2::Digest>::digest(public_key.as_ref()).into())
+}
+
+#[test]
+fn test_public_key_sha256() {
+    use super::test_certs;
+
+    let certificate = parse_certificates(test_certs::SERVER_CERTIFICATE)
+        .unwrap()
+        .remove(0);
+    assert_eq!(
+        public_key_sha256(&certificate).unwrap(),
+        test_certs::SERVER_PUBLIC_KEY_SHA256
+    );
+
+    let truncated = CertificateDer::from(certificate.get(..certificate.len() / 2).unwrap());
+    assert!(public_key_sha256(&truncated).is_err());
+
+    // A `SEQUENCE` claiming to be longer than it is:
+    let malformed = CertificateDer::from(&[0x30, 0x82, 0xff, 0xff, 0x30, 0x00][..]);
+    assert!(public_key_sha256(&malformed).is_err());
+}
@@ -1,3 +1,9 @@
+use std::{sync::Arc, time::Instant};
+
+use tungstenite::http::Uri;
+
+use crate::{CloseInfo, Error, Keepalive, OpenInfo, RedirectPolicy, Result, WsMessage};
+
 impl From<crate::Options> for tungstenite::protocol::WebSocketConfig {
     fn from(options: crate::Options) -> Self {
         let crate::Options {
@@ -27,3 +33,384 @@ pub fn into_requester(
     }
     client_request
 }
+
+/// Convert a `tungstenite` error into ours.
+///
+/// Deliberately not a `From` impl, so that `tungstenite` stays out of our public API.
+pub fn from_tungstenite(err: tungstenite::Error) -> Error {
+    use tungstenite::error::UrlError;
+
+    match err {
+        tungstenite::Error::Io(err) => Error::Io(Arc::new(err)),
+        tungstenite::Error::Tls(err) => Error::Tls(Arc::new(err)),
+        tungstenite::Error::Url(UrlError::TlsFeatureNotEnabled) => Error::Tls(Arc::new(err)),
+        tungstenite::Error::Url(_) => Error::Connect(Arc::new(err)),
+        tungstenite::Error::Http(response) => {
+            let mut body = response.body().clone().unwrap_or_default();
+            body.truncate(crate::error::MAX_REJECTION_BODY_SIZE);
+            Error::HandshakeRejected {
+                status: response.status().as_u16(),
+                headers: header_pairs(response.headers()),
+                body,
+            }
+        }
+        tungstenite::Error::ConnectionClosed
+        | tungstenite::Error::AlreadyClosed
+        | tungstenite::Error::Capacity(_)
+        | tungstenite::Error::Protocol(_)
+        | tungstenite::Error::WriteBufferFull(_)
+        | tungstenite::Error::Utf8(_)
+        | tungstenite::Error::AttackAttempt
+        | tungstenite::Error::HttpFormat(_) => Error::Protocol(Arc::new(err)),
+    }
+}
+
+/// Parse the URL we were asked to connect to.
+pub fn parse_uri(url: &str) -> Result<tungstenite::http::Uri> {
+    url.parse().map_err(|err| Error::InvalidUrl {
+        url: url.to_owned(),
+        source: Arc::new(err),
+    })
+}
+
+/// Classify an error that happened while connecting to `url`.
+///
+/// I/O errors at this stage mean DNS or TCP failures, not a broken connection.
+pub fn connect_error(url: &str, err: tungstenite::Error) -> Error {
+    use tungstenite::error::UrlError;
+
+    match err {
+        tungstenite::Error::Io(err) if is_tls_error(&err) => Error::Tls(Arc::new(err)),
+        tungstenite::Error::Io(err) => Error::Connect(Arc::new(err)),
+        tungstenite::Error::Url(
+            UrlError::NoHostName
+            | UrlError::EmptyHostName
+            | UrlError::UnsupportedUrlScheme
+            | UrlError::NoPathOrQuery,
+        ) => Error::InvalidUrl {
+            url: url.to_owned(),
+            source: Arc::new(err),
+        },
+        err => from_tungstenite(err),
+    }
+}
+
+/// `rustls` reports a failed TLS handshake, e.g. an untrusted certificate, as an I/O error,
+/// and so do we for [`crate::tls::PinningNotSupported`].
+#[cfg(any(
+    feature = "tls",
+    feature = "rustls-tls-native-roots",
+    feature = "native-tls"
+))]
+fn is_tls_error(err: &std::io::Error) -> bool {
+    err.get_ref().is_some_and(|inner| {
+        #[cfg(any(feature = "tls", feature = "rustls-tls-native-roots"))]
+        if inner.is::<rustls::Error>() {
+            return true;
+        }
+
+        #[cfg(feature = "native-tls")]
+        if inner.is::<crate::tls::PinningNotSupported>() {
+            return true;
+        }
+
+        false
+    })
+}
+
+#[cfg(not(any(
+    feature = "tls",
+    feature = "rustls-tls-native-roots",
+    feature = "native-tls"
+)))]
+fn is_tls_error(_err: &std::io::Error) -> bool {
+    false
+}
+
+/// Convert an outgoing message into what `tungstenite` sends.
+pub fn into_tungstenite_message(message: WsMessage) -> tungstenite::protocol::Message {
+    match message {
+        WsMessage::Text(text) => tungstenite::protocol::Message::Text(text.into()),
+        WsMessage::Binary(data) => tungstenite::protocol::Message::Binary(data.into()),
+        WsMessage::Ping(data) => tungstenite::protocol::Message::Ping(data.into()),
+        WsMessage::Pong(data) => tungstenite::protocol::Message::Pong(data.into()),
+        WsMessage::Close { code, reason } => {
+            tungstenite::protocol::Message::Close(Some(tungstenite::protocol::CloseFrame {
+                code: code.into(),
+                reason: reason.into(),
+            }))
+        }
+        WsMessage::Unknown(_) => panic!("You cannot send WsMessage::Unknown"),
+    }
+}
+
+/// What we report in [`crate::WsEvent::Opened`].
+pub fn open_info(uri: &Uri, response: &tungstenite::handshake::client::Response) -> OpenInfo {
+    let headers = response.headers();
+    let header = |name: &str| {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    };
+
+    OpenInfo {
+        status: response.status().as_u16(),
+        headers: header_pairs(headers),
+        subprotocol: header("sec-websocket-protocol"),
+        extensions: header("sec-websocket-extensions"),
+        url: uri.to_string(),
+    }
+}
+
+/// Where to go next, if the server answered our handshake with `uri` with a redirect
+/// that `policy` lets us follow.
+///
+/// `num_redirects` is how many redirects we have already followed.
+pub fn redirect_target(
+    uri: &Uri,
+    response: &tungstenite::http::Response<Option<Vec<u8>>>,
+    policy: &RedirectPolicy,
+    num_redirects: usize,
+) -> Option<Uri> {
+    // Not 300 Multiple Choices or 304 Not Modified, which don't tell us where to go:
+    if !matches!(response.status().as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+    if policy.max_redirects <= num_redirects {
+        log::debug!("Not following more than {} redirects", policy.max_redirects);
+        return None;
+    }
+
+    let Some(location) = response.headers().get("Location") else {
+        log::warn!("No `Location` found in redirect");
+        return None;
+    };
+    let Some(target) = location
+        .to_str()
+        .ok()
+        .and_then(|location| resolve_location(uri, location))
+    else {
+        log::warn!("Invalid `Location` in redirect: {location:?}");
+        return None;
+    };
+
+    if !policy.allow_cross_origin && origin(&target) != origin(uri) {
+        log::debug!("Not following cross-origin redirect from {uri} to {target}");
+        return None;
+    }
+    if let Some(approve) = &policy.approve {
+        if !approve.approves(&uri.to_string(), &target.to_string()) {
+            log::debug!("Redirect from {uri} to {target} was not approved");
+            return None;
+        }
+    }
+
+    log::debug!("Redirecting to {target}");
+    Some(target)
+}
+
+/// `location` can be relative to `base`, and use `http(s)` instead of `ws(s)`.
+fn resolve_location(base: &Uri, location: &str) -> Option<Uri> {
+    let mut parts = location.parse::<Uri>().ok()?.into_parts();
+    parts.scheme = match parts.scheme.take() {
+        None => base.scheme().cloned(),
+        Some(scheme) => match scheme.as_str() {
+            "http" => Some("ws".parse().ok()?),
+            "https" => Some("wss".parse().ok()?),
+            _ => Some(scheme),
+        },
+    };
+    if parts.authority.is_none() {
+        parts.authority = base.authority().cloned();
+    }
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some("/".parse().ok()?);
+    }
+    Uri::from_parts(parts).ok()
+}
+
+/// The scheme, host and port of `uri`.
+fn origin(uri: &Uri) -> (Option<&str>, Option<&str>, Option<u16>) {
+    let port = uri.port_u16().or(match uri.scheme_str() {
+        Some("ws") => Some(80),
+        Some("wss") => Some(443),
+        _ => None,
+    });
+    (uri.scheme_str(), uri.host(), port)
+}
+
+fn header_pairs(headers: &tungstenite::http::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Convert a received close frame into a [`CloseInfo`].
+pub fn close_info(frame: Option<tungstenite::protocol::CloseFrame>) -> CloseInfo {
+    match frame {
+        Some(frame) => CloseInfo {
+            code: frame.code.into(),
+            reason: frame.reason.to_string(),
+            was_clean: true,
+        },
+        None => CloseInfo {
+            code: 1005, // No Status Received
+            reason: String::new(),
+            was_clean: true,
+        },
+    }
+}
+
+/// Keeps track of when to send [`crate::Options::keepalive`] pings,
+/// and when to give up on the server.
+pub struct KeepaliveTimer {
+    keepalive: Keepalive,
+    next_ping: Instant,
+
+    /// When we sent the first ping that is still waiting for an answer.
+    ping_sent_at: Option<Instant>,
+}
+
+impl KeepaliveTimer {
+    pub fn new(keepalive: Keepalive) -> Self {
+        Self {
+            next_ping: Instant::now() + keepalive.interval,
+            keepalive,
+            ping_sent_at: None,
+        }
+    }
+
+    /// Call when anything was received from the server.
+    pub fn on_receive(&mut self) {
+        self.ping_sent_at = None;
+    }
+
+    /// Returns `true` if it is time to send a ping,
+    /// or an error if the server has not answered in time.
+    pub fn check(&mut self) -> Result<bool> {
+        let now = Instant::now();
+        let timeout = self.keepalive.timeout;
+        if self
+            .ping_sent_at
+            .is_some_and(|ping_sent_at| ping_sent_at + timeout <= now)
+        {
+            return Err(Error::KeepaliveTimeout { timeout });
+        }
+        if self.next_ping <= now {
+            self.next_ping = now + self.keepalive.interval;
+            self.ping_sent_at.get_or_insert(now);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// When [`Self::check`] should be called next.
+    pub fn next_deadline(&self) -> Instant {
+        match self.ping_sent_at {
+            Some(ping_sent_at) => self.next_ping.min(ping_sent_at + self.keepalive.timeout),
+            None => self.next_ping,
+        }
+    }
+}
+
+#[test]
+fn test_redirect_target() {
+    let uri: Uri = "ws://example.com/socket".parse().unwrap();
+    let redirect = |status: u16, location: &str| {
+        let response = tungstenite::http::Response::builder()
+            .status(status)
+            .header("Location", location)
+            .body(None)
+            .unwrap();
+        redirect_target(&uri, &response, &RedirectPolicy::default(), 0).map(|uri| uri.to_string())
+    };
+
+    assert_eq!(
+        redirect(301, "/elsewhere").as_deref(),
+        Some("ws://example.com/elsewhere")
+    );
+    assert_eq!(
+        redirect(308, "https://example.org/").as_deref(),
+        Some("wss://example.org/")
+    );
+    assert_eq!(redirect(300, "/elsewhere"), None);
+    assert_eq!(redirect(304, "/elsewhere"), None);
+    assert_eq!(redirect(200, "/elsewhere"), None);
+}
+
+/// Listen on a random local port.
+///
+/// Returns the listener and its `ws://` URL.
+#[cfg(test)]
+pub fn test_listener() -> (std::net::TcpListener, String) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+/// Start a server that hands each incoming connection to `serve`, one after the other.
+///
+/// Returns its `ws://` URL.
+#[cfg(test)]
+pub fn spawn_test_server(mut serve: impl FnMut(std::net::TcpStream) + Send + 'static) -> String {
+    let (listener, url) = test_listener();
+    std::thread::Builder::new()
+        .name("test_server".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap());
+            }
+        })
+        .unwrap();
+    url
+}
+
+/// Accept a WebSocket connection, and send back every text message on it.
+#[cfg(test)]
+pub fn echo(stream: std::net::TcpStream) {
+    let mut socket = tungstenite::accept(stream).unwrap();
+    while let Ok(msg) = socket.read() {
+        if msg.is_text() {
+            socket.send(msg).ok();
+        }
+    }
+}
+
+/// Start a WebSocket server, and a server on another port that redirects to it.
+///
+/// Returns their URLs.
+#[cfg(test)]
+pub fn spawn_redirect_servers() -> (String, String) {
+    use std::io::{BufRead as _, Write as _};
+
+    let server_url = spawn_test_server(|stream| {
+        let mut socket = tungstenite::accept(stream).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    // Redirects everything to the server above, on another port:
+    let redirector_url = spawn_test_server({
+        let server_url = server_url.clone();
+        move |mut stream| {
+            for line in std::io::BufReader::new(&stream).lines() {
+                if line.unwrap().is_empty() {
+                    break;
+                }
+            }
+            let response = format!("HTTP/1.1 302 Found\r\nLocation: {server_url}\r\n\r\n");
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    (server_url, redirector_url)
+}
@@ -1,9 +1,14 @@
 #![allow(trivial_casts)]
 
-use std::{ops::ControlFlow, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    ops::ControlFlow,
+    rc::Rc,
+};
 use wasm_bindgen::JsValue;
 
-use crate::{EventHandler, Options, Result, WsEvent, WsMessage};
+use crate::{CloseInfo, Error, EventHandler, OpenInfo, Options, Result, WsEvent, WsMessage};
 
 #[allow(clippy::needless_pass_by_value)]
 fn string_from_js_value(s: wasm_bindgen::JsValue) -> String {
@@ -19,7 +24,7 @@ fn string_from_js_string(s: js_sys::JsString) -> String {
 ///
 /// When this is dropped, the connection is closed.
 pub struct WsSender {
-    socket: Option<Rc<web_sys::WebSocket>>,
+    connection: Option<Rc<Connection>>,
 }
 
 impl Drop for WsSender {
@@ -32,35 +37,37 @@ impl WsSender {
     /// Send the message to the server.
     #[allow(clippy::needless_pass_by_ref_mut)]
     pub fn send(&mut self, msg: WsMessage) {
-        if let Some(socket) = &mut self.socket {
-            let result = match msg {
-                WsMessage::Binary(data) => {
-                    socket.set_binary_type(web_sys::BinaryType::Blob);
-                    socket.send_with_u8_array(&data)
-                }
-                WsMessage::Text(text) => socket.send_with_str(&text),
-                unknown => {
-                    panic!("Don't know how to send message: {unknown:?}");
-                }
-            };
-            if let Err(err) = result.map_err(string_from_js_value) {
-                log::error!("Failed to send: {err:?}");
-            }
+        if let Some(connection) = &self.connection {
+            connection.send(msg);
         }
     }
 
+    /// Close the connection with the given close code and reason.
+    ///
+    /// The browser only allows the code `1000`, or a code in the range `3000..=4999`.
+    /// For any other code, we close without a code, and report a [`WsEvent::Error`].
+    ///
+    /// [`WsEvent::Closed`] is reported once the browser has completed the closing handshake.
+    pub fn close_with(&mut self, code: u16, reason: impl Into<String>) {
+        self.send(WsMessage::Close {
+            code,
+            reason: reason.into(),
+        });
+        self.connection = None;
+    }
+
     /// Close the connection.
     ///
     /// This is called automatically when the sender is dropped.
     pub fn close(&mut self) {
-        if let Some(socket) = self.socket.take() {
-            close_socket(&socket);
+        if let Some(connection) = self.connection.take() {
+            connection.close();
         }
     }
 
     /// Forget about this sender without closing the connection.
     pub fn forget(mut self) {
-        self.socket = None;
+        self.connection = None;
     }
 }
 
@@ -68,138 +75,407 @@ pub(crate) fn ws_receive_impl(url: String, options: Options, on_event: EventHand
     ws_connect_impl(url, options, on_event).map(|sender| sender.forget())
 }
 
-#[allow(clippy::needless_pass_by_value)] // For consistency with the native version
 pub(crate) fn ws_connect_impl(
     url: String,
     options: Options,
     on_event: EventHandler,
 ) -> Result<WsSender> {
-    // Based on https://wasm-bindgen.github.io/wasm-bindgen/examples/websockets.html
+    if let Some(heartbeat) = &options.heartbeat {
+        heartbeat.check()?;
+    }
 
-    use wasm_bindgen::closure::Closure;
-    use wasm_bindgen::JsCast as _;
+    let connection = Rc::new(Connection {
+        url,
+        options,
+        // Allow it to be shared by the different callbacks:
+        on_event: on_event.into(),
+        socket: RefCell::new(None),
+        closed_by_us: Cell::new(false),
+        attempt: Cell::new(0),
+        queued: RefCell::new(VecDeque::new()),
+        generation: Cell::new(0),
+        heartbeats_sent: Cell::new(0),
+        heartbeats_answered: Cell::new(0),
+    });
+    connection.open()?;
 
-    // Connect to a server
-    let socket =
-        web_sys::WebSocket::new_with_str_sequence(&url, &JsValue::from(options.subprotocols))
-            .map_err(string_from_js_value)?;
-    let socket = Rc::new(socket);
-
-    // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
-    socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
-
-    // Allow it to be shared by the different callbacks:
-    let on_event: Rc<dyn Send + Fn(WsEvent) -> ControlFlow<()>> = on_event.into();
-
-    // onmessage callback
-    {
-        let on_event = on_event.clone();
-        let socket2 = socket.clone();
-        let onmessage_callback = Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
-            // Handle difference Text/Binary,...
-            let control = if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
-                let array = js_sys::Uint8Array::new(&abuf);
-                on_event(WsEvent::Message(WsMessage::Binary(array.to_vec())))
-            } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
-                // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
-                let file_reader = web_sys::FileReader::new().expect("Failed to create FileReader");
-                let file_reader_clone = file_reader.clone();
-                // create onLoadEnd callback
-                let on_event = on_event.clone();
-                let socket3 = socket2.clone();
-                let onloadend_cb = Closure::wrap(Box::new(move |_e: web_sys::ProgressEvent| {
-                    let control = match file_reader_clone.result() {
-                        Ok(file_reader) => {
-                            let array = js_sys::Uint8Array::new(&file_reader);
-                            on_event(WsEvent::Message(WsMessage::Binary(array.to_vec())))
+    Ok(WsSender {
+        connection: Some(connection),
+    })
+}
+
+/// State shared by the [`WsSender`] and the `WebSocket` callbacks.
+///
+/// Outlives the `WebSocket` when we reconnect.
+struct Connection {
+    url: String,
+    options: Options,
+    on_event: Rc<dyn Send + Fn(WsEvent) -> ControlFlow<()>>,
+
+    /// The current socket. Replaced when reconnecting.
+    socket: RefCell<Option<web_sys::WebSocket>>,
+
+    /// Set when we close the connection, so that we don't reconnect.
+    closed_by_us: Cell<bool>,
+
+    /// Reconnection attempts since we were last connected.
+    attempt: Cell<u32>,
+
+    /// Messages sent while reconnecting, to be sent once we are connected again.
+    queued: RefCell<VecDeque<WsMessage>>,
+
+    /// Bumped whenever the socket is replaced or abandoned, so that stale heartbeat timers stop.
+    generation: Cell<u32>,
+
+    /// Number of [`Options::heartbeat`]s sent.
+    heartbeats_sent: Cell<u32>,
+
+    /// The value of `heartbeats_sent` when we last got a heartbeat reply.
+    heartbeats_answered: Cell<u32>,
+}
+
+impl Connection {
+    /// Open a new `WebSocket`, replacing the current one.
+    fn open(self: &Rc<Self>) -> Result<()> {
+        // Based on https://wasm-bindgen.github.io/wasm-bindgen/examples/websockets.html
+
+        use wasm_bindgen::closure::Closure;
+        use wasm_bindgen::JsCast as _;
+
+        // Connect to a server
+        let socket = web_sys::WebSocket::new_with_str_sequence(
+            &self.url,
+            &JsValue::from(self.options.subprotocols.clone()),
+        )
+        .map_err(|err| Error::Web(string_from_js_value(err)))?;
+
+        // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
+        socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        self.generation.set(self.generation.get().wrapping_add(1));
+
+        // onmessage callback
+        {
+            let connection = self.clone();
+            let onmessage_callback = Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
+                // Handle difference Text/Binary,...
+                let control = if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    let array = js_sys::Uint8Array::new(&abuf);
+                    connection.on_message(WsMessage::Binary(array.to_vec()))
+                } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
+                    // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
+                    let file_reader =
+                        web_sys::FileReader::new().expect("Failed to create FileReader");
+                    let file_reader_clone = file_reader.clone();
+                    // create onLoadEnd callback
+                    let connection = connection.clone();
+                    let onloadend_cb = Closure::wrap(Box::new(move |_e: web_sys::ProgressEvent| {
+                        let control = match file_reader_clone.result() {
+                            Ok(file_reader) => {
+                                let array = js_sys::Uint8Array::new(&file_reader);
+                                connection.on_message(WsMessage::Binary(array.to_vec()))
+                            }
+                            Err(err) => (connection.on_event)(WsEvent::Error(Error::Web(format!(
+                                "Failed to read binary blob: {}",
+                                string_from_js_value(err)
+                            )))),
+                        };
+                        if control.is_break() {
+                            connection.close();
                         }
-                        Err(err) => on_event(WsEvent::Error(format!(
-                            "Failed to read binary blob: {}",
-                            string_from_js_value(err)
-                        ))),
-                    };
-                    if control.is_break() {
-                        close_socket(&socket3);
+                    })
+                        as Box<dyn FnMut(web_sys::ProgressEvent)>);
+                    file_reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
+                    file_reader
+                        .read_as_array_buffer(&blob)
+                        .expect("blob not readable");
+                    onloadend_cb.forget();
+                    ControlFlow::Continue(())
+                } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
+                    connection.on_message(WsMessage::Text(string_from_js_string(txt)))
+                } else {
+                    log::debug!("Unknown websocket message received: {:?}", e.data());
+                    connection.on_message(WsMessage::Unknown(string_from_js_value(e.data())))
+                };
+                if control.is_break() {
+                    connection.close();
+                }
+            })
+                as Box<dyn FnMut(web_sys::MessageEvent)>);
+
+            // set message event handler on WebSocket
+            socket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+
+            // forget the callback to keep it alive
+            onmessage_callback.forget();
+        }
+
+        {
+            let on_event = self.on_event.clone();
+            let onerror_callback =
+                Closure::wrap(Box::new(move |error_event: web_sys::ErrorEvent| {
+                    // using reflect instead of error_event.message() to avoid panic on null
+                    let message =
+                        js_sys::Reflect::get(&error_event, &"message".into()).unwrap_or_default();
+                    let error =
+                        js_sys::Reflect::get(&error_event, &"error".into()).unwrap_or_default();
+                    log::error!("error event: {message:?}: {error:?}");
+                    #[expect(
+                        unused_must_use,
+                        reason = "we intentionally ignore the return of `on_event`"
+                    )]
+                    on_event(WsEvent::Error(Error::Web(
+                        message
+                            .as_string()
+                            .unwrap_or_else(|| "Unknown error".to_owned()),
+                    )));
+                }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
+            socket.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+            onerror_callback.forget();
+        }
+
+        {
+            let connection = self.clone();
+            let onopen_callback = Closure::wrap(Box::new(move |_| {
+                connection.attempt.set(0);
+                let socket = connection.socket.borrow().clone();
+                let open_info = socket.map(|socket| OpenInfo {
+                    status: 101,
+                    headers: vec![],
+                    subprotocol: Some(socket.protocol()).filter(|protocol| !protocol.is_empty()),
+                    extensions: Some(socket.extensions())
+                        .filter(|extensions| !extensions.is_empty()),
+                    url: socket.url(),
+                });
+                let control = (connection.on_event)(WsEvent::Opened(open_info.unwrap_or_default()));
+                if control.is_break() {
+                    connection.close();
+                } else {
+                    connection.send_queued();
+                    connection.schedule_heartbeat();
+                }
+            })
+                as Box<dyn FnMut(wasm_bindgen::JsValue)>);
+            socket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+            onopen_callback.forget();
+        }
+
+        {
+            let connection = self.clone();
+            let onclose_callback =
+                Closure::wrap(Box::new(move |close_event: web_sys::CloseEvent| {
+                    let control = (connection.on_event)(WsEvent::Closed(CloseInfo {
+                        code: close_event.code(),
+                        reason: close_event.reason(),
+                        was_clean: close_event.was_clean(),
+                    }));
+                    if control.is_continue() && !connection.closed_by_us.get() {
+                        connection.reconnect_later();
                     }
-                })
-                    as Box<dyn FnMut(web_sys::ProgressEvent)>);
-                file_reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
-                file_reader
-                    .read_as_array_buffer(&blob)
-                    .expect("blob not readable");
-                onloadend_cb.forget();
-                ControlFlow::Continue(())
-            } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                on_event(WsEvent::Message(WsMessage::Text(string_from_js_string(
-                    txt,
-                ))))
-            } else {
-                log::debug!("Unknown websocket message received: {:?}", e.data());
-                on_event(WsEvent::Message(WsMessage::Unknown(string_from_js_value(
-                    e.data(),
-                ))))
+                }) as Box<dyn FnMut(web_sys::CloseEvent)>);
+            socket.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+            onclose_callback.forget();
+        }
+
+        *self.socket.borrow_mut() = Some(socket);
+
+        Ok(())
+    }
+
+    /// Schedule a reconnection according to [`Options::reconnect`].
+    fn reconnect_later(self: &Rc<Self>) {
+        let Some(reconnect) = &self.options.reconnect else {
+            return;
+        };
+        let attempt = self.attempt.get() + 1;
+        self.attempt.set(attempt);
+        let Some(delay) = reconnect.delay(attempt) else {
+            log::debug!("Giving up after {} reconnection attempts", attempt - 1);
+            return;
+        };
+        if (self.on_event)(WsEvent::Reconnecting { attempt, delay }).is_break() {
+            return;
+        }
+
+        let connection = self.clone();
+        let result = set_timeout(delay, move || {
+            if connection.closed_by_us.get() {
+                return; // closed while we were waiting
+            }
+            if let Err(err) = connection.open() {
+                if (connection.on_event)(WsEvent::Error(err)).is_continue() {
+                    connection.reconnect_later();
+                }
+            }
+        });
+        if let Err(err) = result {
+            log::error!(
+                "Failed to schedule reconnection: {}",
+                string_from_js_value(err)
+            );
+        }
+    }
+
+    /// Pass an incoming message on to the event handler,
+    /// noting if it is a reply to our heartbeat.
+    fn on_message(&self, msg: WsMessage) -> ControlFlow<()> {
+        if let Some(heartbeat) = &self.options.heartbeat {
+            if heartbeat.reply.matches(&msg) {
+                self.heartbeats_answered.set(self.heartbeats_sent.get());
+            }
+        }
+        (self.on_event)(WsEvent::Message(msg))
+    }
+
+    /// Send the next [`Options::heartbeat`] after its interval.
+    fn schedule_heartbeat(self: &Rc<Self>) {
+        let Some(heartbeat) = &self.options.heartbeat else {
+            return;
+        };
+        let connection = self.clone();
+        let generation = self.generation.get();
+        if let Err(err) = set_timeout(heartbeat.interval, move || {
+            connection.send_heartbeat(generation);
+        }) {
+            log::error!(
+                "Failed to schedule heartbeat: {}",
+                string_from_js_value(err)
+            );
+        }
+    }
+
+    fn send_heartbeat(self: &Rc<Self>, generation: u32) {
+        let Some(heartbeat) = &self.options.heartbeat else {
+            return;
+        };
+        if self.generation.get() != generation {
+            return; // The socket was replaced or abandoned
+        }
+        {
+            let socket = self.socket.borrow();
+            let Some(socket) = &*socket else {
+                return;
             };
-            if control.is_break() {
-                close_socket(&socket2);
+            if socket.ready_state() != web_sys::WebSocket::OPEN {
+                return; // Closing or closed
             }
-        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
-
-        // set message event handler on WebSocket
-        socket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-
-        // forget the callback to keep it alive
-        onmessage_callback.forget();
-    }
-
-    {
-        let on_event = on_event.clone();
-        let onerror_callback = Closure::wrap(Box::new(move |error_event: web_sys::ErrorEvent| {
-            // using reflect instead of error_event.message() to avoid panic on null
-            let message = js_sys::Reflect::get(&error_event, &"message".into()).unwrap_or_default();
-            let error = js_sys::Reflect::get(&error_event, &"error".into()).unwrap_or_default();
-            log::error!("error event: {message:?}: {error:?}");
-            #[expect(
-                unused_must_use,
-                reason = "we intentionally ignore the return of `on_event`"
-            )]
-            on_event(WsEvent::Error(
-                message
-                    .as_string()
-                    .unwrap_or_else(|| "Unknown error".to_owned()),
-            ));
-        }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
-        socket.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-        onerror_callback.forget();
-    }
-
-    {
-        let socket2 = socket.clone();
-        let on_event = on_event.clone();
-        let onopen_callback = Closure::wrap(Box::new(move |_| {
-            let control = on_event(WsEvent::Opened);
-            if control.is_break() {
-                close_socket(&socket2);
+            send_on_socket(socket, heartbeat.payload.clone());
+        }
+
+        let sent = self.heartbeats_sent.get().wrapping_add(1);
+        self.heartbeats_sent.set(sent);
+
+        let connection = self.clone();
+        if let Err(err) = set_timeout(heartbeat.timeout, move || {
+            if connection.generation.get() == generation
+                && !crate::heartbeat_answered(sent, connection.heartbeats_answered.get())
+            {
+                connection.on_heartbeat_timeout();
             }
-        }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
-        socket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-        onopen_callback.forget();
+        }) {
+            log::error!(
+                "Failed to schedule heartbeat timeout: {}",
+                string_from_js_value(err)
+            );
+        }
+
+        self.schedule_heartbeat();
     }
 
-    {
-        let onclose_callback = Closure::wrap(Box::new(move |_| {
-            #[expect(
-                unused_must_use,
-                reason = "we intentionally ignore the return of `on_event`"
-            )]
-            on_event(WsEvent::Closed);
-        }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
-        socket.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
-        onclose_callback.forget();
+    /// Give up on a connection that stopped answering our heartbeats.
+    ///
+    /// Like on native, we report an error followed by an abnormal closure,
+    /// instead of waiting for the browser to notice the connection is dead.
+    fn on_heartbeat_timeout(self: &Rc<Self>) {
+        let Some(heartbeat) = &self.options.heartbeat else {
+            return;
+        };
+        log::debug!("No heartbeat reply within {:?}", heartbeat.timeout);
+
+        self.generation.set(self.generation.get().wrapping_add(1));
+        if let Some(socket) = &*self.socket.borrow() {
+            socket.set_onmessage(None);
+            socket.set_onerror(None);
+            socket.set_onclose(None);
+            close_socket(socket);
+        }
+
+        let error = Error::KeepaliveTimeout {
+            timeout: heartbeat.timeout,
+        };
+        if (self.on_event)(WsEvent::Error(error)).is_break() {
+            self.closed_by_us.set(true);
+            return;
+        }
+        let control = (self.on_event)(WsEvent::Closed(CloseInfo::abnormal()));
+        if control.is_continue() && !self.closed_by_us.get() {
+            self.reconnect_later();
+        }
     }
 
-    Ok(WsSender {
-        socket: Some(socket),
-    })
+    fn send(&self, msg: WsMessage) {
+        if let WsMessage::Close { code, reason } = msg {
+            self.closed_by_us.set(true);
+            if let Some(socket) = &*self.socket.borrow() {
+                if let Err(err) = socket.close_with_code_and_reason(code, &reason) {
+                    // E.g. a code the browser doesn't allow. Don't leave the socket open:
+                    let err = string_from_js_value(err);
+                    log::error!("Failed to close with code {code}: {err:?}");
+                    close_socket(socket);
+                    #[expect(
+                        unused_must_use,
+                        reason = "we intentionally ignore the return of `on_event`"
+                    )]
+                    (self.on_event)(WsEvent::Error(Error::Web(format!(
+                        "Failed to close with code {code}: {err}"
+                    ))));
+                }
+            }
+            return;
+        }
+
+        let socket = self.socket.borrow();
+        let Some(socket) = &*socket else {
+            return;
+        };
+        if self.options.reconnect.is_some() && socket.ready_state() != web_sys::WebSocket::OPEN {
+            self.queued.borrow_mut().push_back(msg);
+            return;
+        }
+        send_on_socket(socket, msg);
+    }
+
+    /// Send the messages that were sent while we were reconnecting.
+    fn send_queued(&self) {
+        if let Some(socket) = &*self.socket.borrow() {
+            for msg in self.queued.borrow_mut().drain(..) {
+                send_on_socket(socket, msg);
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.closed_by_us.set(true);
+        if let Some(socket) = &*self.socket.borrow() {
+            close_socket(socket);
+        }
+    }
+}
+
+fn send_on_socket(socket: &web_sys::WebSocket, msg: WsMessage) {
+    let result = match msg {
+        WsMessage::Binary(data) => {
+            socket.set_binary_type(web_sys::BinaryType::Blob);
+            socket.send_with_u8_array(&data)
+        }
+        WsMessage::Text(text) => socket.send_with_str(&text),
+        unknown => {
+            panic!("Don't know how to send message: {unknown:?}");
+        }
+    };
+    if let Err(err) = result.map_err(string_from_js_value) {
+        log::error!("Failed to send: {err:?}");
+    }
 }
 
 fn close_socket(socket: &web_sys::WebSocket) {
@@ -209,3 +485,23 @@ fn close_socket(socket: &web_sys::WebSocket) {
         log::debug!("Closed WebSocket");
     }
 }
+
+/// Call `callback` after `delay`, using the global `setTimeout`.
+///
+/// Unlike `web_sys::Window`, this also works in web workers.
+fn set_timeout(
+    delay: std::time::Duration,
+    callback: impl FnOnce() + 'static,
+) -> std::result::Result<(), JsValue> {
+    use wasm_bindgen::JsCast as _;
+
+    let global = js_sys::global();
+    let set_timeout: js_sys::Function =
+        js_sys::Reflect::get(&global, &"setTimeout".into())?.dyn_into()?;
+    set_timeout.call2(
+        &global,
+        &wasm_bindgen::closure::Closure::once_into_js(callback),
+        &JsValue::from_f64(delay.as_secs_f64() * 1000.0),
+    )?;
+    Ok(())
+}
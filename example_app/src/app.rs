@@ -67,8 +67,14 @@ impl ExampleApp {
                 self.error.clear();
             }
             Err(error) => {
-                log::error!("Failed to connect to {:?}: {}", &self.url, error);
-                self.error = error;
+                let mut message = error.to_string();
+                let mut source = std::error::Error::source(&error);
+                while let Some(cause) = source {
+                    message += &format!(": {cause}");
+                    source = cause.source();
+                }
+                log::error!("Failed to connect to {:?}: {}", &self.url, message);
+                self.error = message;
             }
         }
     }